@@ -1,6 +1,6 @@
 use serde::Deserialize;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env,
     fs::File,
     io::{BufWriter, Write},
@@ -8,7 +8,14 @@ use std::{
 };
 
 #[derive(Deserialize)]
-#[allow(unused)]
+struct DTIPropEntry {
+    name: String,
+    // Same as the PropType variant names
+    prop_type: String,
+    attr: u32,
+}
+
+#[derive(Deserialize)]
 struct DTIEntry {
     address: u64,
     parent_address: u64,
@@ -18,6 +25,9 @@ struct DTIEntry {
     size: u64,
 
     file_extension: Option<String>,
+
+    #[serde(default)]
+    props: Vec<DTIPropEntry>,
 }
 
 fn create_clean_name(name: &str) -> String {
@@ -36,28 +46,54 @@ fn build_dti_map() {
     );
 
     let mut map = phf_codegen::Map::new();
+    // Both the real and the clean names, for DTI::from_str
+    let mut names: HashMap<String, u32> = HashMap::new();
 
     let dtis = std::fs::read_to_string("src/dti.txt").unwrap();
+    let entries: Vec<DTIEntry> = dtis
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    // Parents are referenced by address
+    let hashes_by_address: HashMap<u64, u32> = entries
+        .iter()
+        .map(|entry| (entry.address, entry.hash))
+        .collect();
+
     // some dti entries are duplicated for some reason. potentially due to TGAAC
     // having two games packaged into one executable
     let mut handled_entries: HashSet<u32> = HashSet::new();
 
     writeln!(&mut out_file, "#[allow(non_upper_case_globals)]").unwrap();
     writeln!(&mut out_file, "pub mod generated {{").unwrap();
-    for line in dtis.lines() {
-        let entry: DTIEntry = serde_json::from_str(line).unwrap();
+    for entry in &entries {
         if !handled_entries.contains(&entry.hash) {
             handled_entries.insert(entry.hash);
 
             let clean_name = create_clean_name(&entry.name);
+            let parent_hash = hashes_by_address.get(&entry.parent_address);
+            let props: Vec<String> = entry
+                .props
+                .iter()
+                .map(|prop| {
+                    format!(
+                        "super::DTIProp {{ name: {:?}, prop_type: super::PropType::{}, attr: {} }}",
+                        prop.name, prop.prop_type, prop.attr
+                    )
+                })
+                .collect();
 
             writeln!(
                 &mut out_file,
-                "pub const {}: super::DTI = super::DTI {{ name: {:?}, hash: {}, file_ext: {:?} }};",
-                clean_name, &entry.name, entry.hash, entry.file_extension
+                "pub const {}: super::DTI = super::DTI {{ name: {:?}, hash: {}, size: {}, file_ext: {:?}, parent_hash: {:?}, props: &[{}] }};",
+                clean_name, &entry.name, entry.hash, entry.size, entry.file_extension, parent_hash, props.join(", ")
             )
             .unwrap();
 
+            names.insert(entry.name.clone(), entry.hash);
+            names.entry(clean_name.clone()).or_insert(entry.hash);
+
             let formatted_entry = clean_name.to_string();
 
             map.entry(entry.hash, &formatted_entry);
@@ -71,6 +107,19 @@ fn build_dti_map() {
     )
     .unwrap();
 
+    let mut names_map = phf_codegen::Map::new();
+    for (name, hash) in &names {
+        names_map.entry(name.as_str(), &hash.to_string());
+    }
+
+    writeln!(&mut out_file).unwrap();
+    write!(
+        &mut out_file,
+        "pub(super) const DTI_NAMES: phf::Map<&'static str, u32> = {};",
+        names_map.build()
+    )
+    .unwrap();
+
     writeln!(&mut out_file, "}}").unwrap();
 }
 
@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{Cursor, Read, Seek},
+};
+
+use crate::{
+    mtserializer::{self, prp_file_to_mtserializer},
+    rarchive::{ArchiveFile, ResourceInfo},
+    resource_path::ResourcePath,
+    rguimessage::GuiMessageFile,
+};
+
+#[derive(Debug)]
+pub enum FieldChange {
+    Quality(u32, u32),
+    Size(u32, u32),
+    // crc32 of the decompressed data
+    ContentHash(u32, u32),
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldChange::Quality(old, new) => write!(f, "quality {} -> {}", old, new),
+            FieldChange::Size(old, new) => write!(f, "size {} -> {}", old, new),
+            FieldChange::ContentHash(old, new) => write!(f, "crc {:08x} -> {:08x}", old, new),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EntryChange {
+    Added {
+        path: String,
+        dti_name: String,
+    },
+    Removed {
+        path: String,
+        dti_name: String,
+    },
+    // Only reported when a path exists once in each archive. Anything else
+    // that changed is in changes.
+    DtiChanged {
+        path: String,
+        old_dti_name: String,
+        new_dti_name: String,
+        changes: Vec<FieldChange>,
+    },
+    Modified {
+        path: String,
+        dti_hash: u32,
+        dti_name: String,
+        changes: Vec<FieldChange>,
+    },
+}
+
+impl Display for EntryChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryChange::Added { path, dti_name } => write!(f, "+ {:?} ({})", path, dti_name),
+            EntryChange::Removed { path, dti_name } => write!(f, "- {:?} ({})", path, dti_name),
+            EntryChange::DtiChanged {
+                path,
+                old_dti_name,
+                new_dti_name,
+                changes,
+            } => {
+                write!(f, "~ {:?}: dti {} -> {}", path, old_dti_name, new_dti_name)?;
+                for change in changes {
+                    write!(f, ", {}", change)?;
+                }
+
+                Ok(())
+            }
+            EntryChange::Modified {
+                path,
+                dti_name,
+                changes,
+                ..
+            } => {
+                write!(f, "~ {:?} ({}):", path, dti_name)?;
+                for (idx, change) in changes.iter().enumerate() {
+                    let separator = if idx == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, change)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+fn field_changes<A: Read + Seek, B: Read + Seek>(
+    archive_a: &ArchiveFile<A>,
+    info_a: &ResourceInfo,
+    archive_b: &ArchiveFile<B>,
+    info_b: &ResourceInfo,
+) -> anyhow::Result<Vec<FieldChange>> {
+    let mut changes = vec![];
+
+    if info_a.quality() != info_b.quality() {
+        changes.push(FieldChange::Quality(info_a.quality(), info_b.quality()));
+    }
+
+    if info_a.size_uncompressed() != info_b.size_uncompressed() {
+        changes.push(FieldChange::Size(
+            info_a.size_uncompressed(),
+            info_b.size_uncompressed(),
+        ));
+    }
+
+    // Identical compressed data can't decompress to anything different
+    let compressed_a = archive_a.get_resource_compressed(info_a)?;
+    let compressed_b = archive_b.get_resource_compressed(info_b)?;
+    if compressed_a != compressed_b {
+        let hash_a = crc32fast::hash(&archive_a.get_resource_by_info(info_a)?.unwrap());
+        let hash_b = crc32fast::hash(&archive_b.get_resource_by_info(info_b)?.unwrap());
+
+        if hash_a != hash_b {
+            changes.push(FieldChange::ContentHash(hash_a, hash_b));
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Compare two archives entry by entry. Entries are matched by path and DTI.
+/// Changes to the entries of archive_a come first, in the order they appear
+/// in it, followed by the entries that were added in archive_b, in its order.
+pub fn diff_archives<A: Read + Seek, B: Read + Seek>(
+    archive_a: &ArchiveFile<A>,
+    archive_b: &ArchiveFile<B>,
+) -> anyhow::Result<Vec<EntryChange>> {
+    let removed: Vec<&ResourceInfo> = archive_a
+        .resource_infos()
+        .iter()
+        .filter(|info_a| {
+            archive_b
+                .resource_info_by_hash(info_a.path(), info_a.dti_hash())
+                .is_none()
+        })
+        .collect();
+    let added: Vec<&ResourceInfo> = archive_b
+        .resource_infos()
+        .iter()
+        .filter(|info_b| {
+            archive_a
+                .resource_info_by_hash(info_b.path(), info_b.dti_hash())
+                .is_none()
+        })
+        .collect();
+
+    // A path that was removed and added once each just had its type changed
+    let count_paths = |infos: &[&ResourceInfo]| {
+        let mut counts: HashMap<ResourcePath, usize> = HashMap::new();
+        for info in infos {
+            *counts.entry(ResourcePath::new(info.path())).or_default() += 1;
+        }
+
+        counts
+    };
+    let removed_counts = count_paths(&removed);
+    let added_counts = count_paths(&added);
+    // Paths are compared ignoring case, like archive lookups
+    let is_dti_change = |path: &str| {
+        let path = ResourcePath::new(path);
+        removed_counts.get(&path) == Some(&1) && added_counts.get(&path) == Some(&1)
+    };
+
+    let mut changes = vec![];
+
+    for info_a in archive_a.resource_infos() {
+        if let Some(info_b) = archive_b.resource_info_by_hash(info_a.path(), info_a.dti_hash()) {
+            let field_changes = field_changes(archive_a, info_a, archive_b, info_b)?;
+            if !field_changes.is_empty() {
+                changes.push(EntryChange::Modified {
+                    path: info_a.path().to_string(),
+                    dti_hash: info_a.dti_hash(),
+                    dti_name: info_a.dti_name(),
+                    changes: field_changes,
+                });
+            }
+        } else if is_dti_change(info_a.path()) {
+            let info_b = added
+                .iter()
+                .find(|info| ResourcePath::new(info.path()) == ResourcePath::new(info_a.path()))
+                .unwrap();
+
+            changes.push(EntryChange::DtiChanged {
+                path: info_a.path().to_string(),
+                old_dti_name: info_a.dti_name(),
+                new_dti_name: info_b.dti_name(),
+                changes: field_changes(archive_a, info_a, archive_b, info_b)?,
+            });
+        } else {
+            changes.push(EntryChange::Removed {
+                path: info_a.path().to_string(),
+                dti_name: info_a.dti_name(),
+            });
+        }
+    }
+
+    for info_b in &added {
+        if !is_dti_change(info_b.path()) {
+            changes.push(EntryChange::Added {
+                path: info_b.path().to_string(),
+                dti_name: info_b.dti_name(),
+            });
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Text representation of a resource for formats that can be parsed, so that
+/// changes can be shown line by line
+pub fn describe_resource(data: &[u8]) -> anyhow::Result<Option<String>> {
+    match data.get(..4) {
+        Some(b"GMD\0") => {
+            let gmd = GuiMessageFile::new(&mut Cursor::new(data))?;
+
+            Ok(Some(serde_json::to_string_pretty(&gmd)?))
+        }
+        Some(b"XFS\0") | Some(b"PRPZ") => {
+            let mut cursor = prp_file_to_mtserializer(&mut Cursor::new(data))?;
+            let class = mtserializer::deserialize(&mut cursor)?;
+
+            Ok(Some(format!("{:#?}", class)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Unified diff between the parsed forms of two versions of a resource, or
+/// None if the format isn't handled by describe_resource
+pub fn diff_resource_contents(data_a: &[u8], data_b: &[u8]) -> anyhow::Result<Option<String>> {
+    let (Some(text_a), Some(text_b)) = (describe_resource(data_a)?, describe_resource(data_b)?)
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        similar::TextDiff::from_lines(&text_a, &text_b)
+            .unified_diff()
+            .context_radius(2)
+            .to_string(),
+    ))
+}
+
+#[test]
+fn test_diff_archives() {
+    use crate::rarchive::ArchiveWriter;
+
+    let build_archive = |files: &[(&str, u32, u32, &[u8])]| {
+        let mut writer = ArchiveWriter::new();
+        for (path, dti_hash, quality, data) in files {
+            writer
+                .add_file_with_hash(path, *dti_hash, *quality, data)
+                .unwrap();
+        }
+
+        let mut archive_bytes = vec![];
+        writer.save(&mut archive_bytes).unwrap();
+
+        ArchiveFile::new(Cursor::new(archive_bytes)).unwrap()
+    };
+
+    let texture_hash = crate::DTIs::rTexture.hash();
+    let model_hash = crate::DTIs::rModel.hash();
+
+    let archive_a = build_archive(&[
+        ("same", texture_hash, 0, b"same"),
+        ("modified", texture_hash, 0, b"old"),
+        ("removed", texture_hash, 0, b"removed"),
+        ("retyped", texture_hash, 0, b"retyped"),
+        ("retyped modified", texture_hash, 0, b"old"),
+    ]);
+    let archive_b = build_archive(&[
+        ("added", texture_hash, 0, b"added"),
+        ("same", texture_hash, 0, b"same"),
+        ("modified", texture_hash, 1, b"new"),
+        // Paths are compared ignoring case
+        ("RETYPED", model_hash, 0, b"retyped"),
+        ("retyped modified", model_hash, 0, b"new"),
+    ]);
+
+    let changes = diff_archives(&archive_a, &archive_b).unwrap();
+    assert_eq!(5, changes.len());
+
+    assert!(matches!(&changes[1], EntryChange::Removed { path, .. } if path == "removed"));
+    assert!(matches!(
+        &changes[2],
+        EntryChange::DtiChanged { path, changes, .. } if path == "retyped" && changes.is_empty()
+    ));
+    assert!(matches!(
+        &changes[3],
+        EntryChange::DtiChanged { path, changes, .. }
+            if path == "retyped modified" && matches!(changes[..], [FieldChange::ContentHash(..)])
+    ));
+    assert!(matches!(&changes[4], EntryChange::Added { path, .. } if path == "added"));
+
+    let EntryChange::Modified { path, changes, .. } = &changes[0] else {
+        panic!("expected modified entry, got {:?}", changes[0]);
+    };
+    assert_eq!("modified", path);
+    assert!(matches!(changes[0], FieldChange::Quality(0, 1)));
+    assert!(matches!(changes[1], FieldChange::ContentHash(..)));
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    rarchive::{ArchiveEntryReader, ArchiveFile, ArchiveFormat, ArchiveKey},
+    resource_path::ResourcePath,
+    DTIs,
+};
+
+#[derive(Serialize, Deserialize)]
+struct IndexedEntry {
+    path: String,
+    dti_hash: u32,
+    size_compressed: u32,
+    size_uncompressed: u32,
+    quality: u32,
+}
+
+/// What the index knows about an archive entry, without opening the archive
+pub struct IndexedResourceInfo {
+    pub path: String,
+    pub dti_hash: u32,
+    pub size_compressed: u32,
+    pub size_uncompressed: u32,
+    pub quality: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedArchive {
+    // Relative to the index root
+    path: PathBuf,
+    // Used to tell if the archive changed since it was indexed
+    modified: SystemTime,
+    size: u64,
+    // In table of contents order
+    entries: Vec<IndexedEntry>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct IndexFile {
+    archives: Vec<IndexedArchive>,
+}
+
+/// Maps every resource in every archive under a directory to the archive it's
+/// in, so that resources can be found without knowing the archive
+pub struct ArchiveIndex {
+    root: PathBuf,
+    archives: Vec<IndexedArchive>,
+    // (path, dti hash) -> (index into archives, index into archive entries)
+    entries: HashMap<(ResourcePath, u32), (usize, usize)>,
+    format: Option<ArchiveFormat>,
+    key: Option<ArchiveKey>,
+
+    open_archives: Mutex<HashMap<usize, Arc<ArchiveFile<File>>>>,
+}
+
+impl ArchiveIndex {
+    /// Index all archives under root. The index is cached in index_path, and
+    /// only archives that changed since it was written are read again. Without
+    /// a format, it's detected for each archive.
+    pub fn build(
+        root: &Path,
+        index_path: &Path,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<Self> {
+        let cached = match std::fs::read(index_path) {
+            Ok(data) => serde_json::from_slice::<IndexFile>(&data).unwrap_or_else(|err| {
+                warn!("ignoring broken archive index {:?}: {}", index_path, err);
+                IndexFile::default()
+            }),
+            Err(_) => IndexFile::default(),
+        };
+
+        let mut cached_archives: HashMap<PathBuf, IndexedArchive> = cached
+            .archives
+            .into_iter()
+            .map(|archive| (archive.path.clone(), archive))
+            .collect();
+
+        let arc_extension = OsStr::new(DTIs::rArchive.file_ext().unwrap());
+        let mut archives = vec![];
+
+        for file in WalkDir::new(root).sort_by_file_name() {
+            let file = file?;
+
+            if !(file.file_type().is_file() && file.path().extension() == Some(arc_extension)) {
+                continue;
+            }
+
+            let relative_path = file.path().strip_prefix(root)?.to_path_buf();
+            let metadata = file.metadata()?;
+            let modified = metadata.modified()?;
+            let size = metadata.len();
+
+            match cached_archives.remove(&relative_path) {
+                Some(cached) if cached.modified == modified && cached.size == size => {
+                    archives.push(cached)
+                }
+                _ => {
+                    debug!("indexing {:?}", relative_path);
+
+                    // e.g. other formats, or encrypted without a key
+                    let archive = match File::open(file.path())
+                        .map_err(anyhow::Error::from)
+                        .and_then(|file| ArchiveFile::open(file, format, key.clone()))
+                    {
+                        Ok(archive) => archive,
+                        Err(err) => {
+                            warn!("skipping archive {:?}: {:#}", file.path(), err);
+                            continue;
+                        }
+                    };
+
+                    archives.push(IndexedArchive {
+                        path: relative_path,
+                        modified,
+                        size,
+                        entries: archive
+                            .resource_infos()
+                            .iter()
+                            .map(|info| IndexedEntry {
+                                path: info.path().to_string(),
+                                dti_hash: info.dti_hash(),
+                                size_compressed: info.size_compressed(),
+                                size_uncompressed: info.size_uncompressed(),
+                                quality: info.quality(),
+                            })
+                            .collect(),
+                    });
+                }
+            }
+        }
+
+        let index_file = IndexFile { archives };
+        std::fs::write(index_path, serde_json::to_vec(&index_file)?)?;
+
+        Ok(Self::new(root, index_file.archives, format, key))
+    }
+
+    fn new(
+        root: &Path,
+        archives: Vec<IndexedArchive>,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> Self {
+        // Archives are in path order, if a resource is in several the first
+        // one wins
+        let mut entries = HashMap::new();
+        for (archive_idx, archive) in archives.iter().enumerate() {
+            for (entry_idx, entry) in archive.entries.iter().enumerate() {
+                entries
+                    .entry((ResourcePath::new(&entry.path), entry.dti_hash))
+                    .or_insert((archive_idx, entry_idx));
+            }
+        }
+
+        debug!(
+            "archive index has {} resources in {} archives",
+            entries.len(),
+            archives.len()
+        );
+
+        Self {
+            root: root.to_path_buf(),
+            archives,
+            entries,
+            format,
+            key,
+            open_archives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn num_archives(&self) -> usize {
+        self.archives.len()
+    }
+
+    pub fn num_resources(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Every indexed resource, as (archive path, archive entry). Resources
+    /// that are in several archives are only returned once.
+    pub fn resource_infos(&self) -> impl Iterator<Item = (PathBuf, IndexedResourceInfo)> + '_ {
+        self.entries.values().map(|(archive_idx, entry_idx)| {
+            let archive = &self.archives[*archive_idx];
+            let entry = &archive.entries[*entry_idx];
+
+            (
+                self.root.join(&archive.path),
+                IndexedResourceInfo {
+                    path: entry.path.clone(),
+                    dti_hash: entry.dti_hash,
+                    size_compressed: entry.size_compressed,
+                    size_uncompressed: entry.size_uncompressed,
+                    quality: entry.quality,
+                },
+            )
+        })
+    }
+
+    /// Path of the archive containing a resource
+    pub fn archive_path(&self, path: &ResourcePath, dti_hash: u32) -> Option<PathBuf> {
+        self.entries
+            .get(&(path.clone(), dti_hash))
+            .map(|(archive_idx, _)| self.root.join(&self.archives[*archive_idx].path))
+    }
+
+    fn open_archive(&self, archive_idx: usize) -> anyhow::Result<Arc<ArchiveFile<File>>> {
+        let mut open_archives = self.open_archives.lock().unwrap();
+
+        if let Some(archive) = open_archives.get(&archive_idx) {
+            return Ok(archive.clone());
+        }
+
+        let fs_path = self.root.join(&self.archives[archive_idx].path);
+        // Not mapped, since archives stay open and can be replaced by the
+        // game or other tools in the meantime
+        let archive = Arc::new(ArchiveFile::open(
+            File::open(fs_path)?,
+            self.format,
+            self.key.clone(),
+        )?);
+        open_archives.insert(archive_idx, archive.clone());
+
+        Ok(archive)
+    }
+
+    pub fn open_resource(
+        &self,
+        path: &ResourcePath,
+        dti_hash: u32,
+    ) -> anyhow::Result<Option<ArchiveEntryReader>> {
+        let Some((archive_idx, entry_idx)) = self.entries.get(&(path.clone(), dti_hash)) else {
+            return Ok(None);
+        };
+
+        let archive = self.open_archive(*archive_idx)?;
+        let info = archive
+            .resource_infos()
+            .get(*entry_idx)
+            .filter(|info| ResourcePath::new(info.path()) == *path && info.dti_hash() == dti_hash)
+            .ok_or_else(|| {
+                anyhow!(
+                    "archive index is out of date for {:?}",
+                    self.archives[*archive_idx].path
+                )
+            })?;
+
+        archive.open_resource_by_info(info)
+    }
+}
+
+#[test]
+fn test_archive_index() {
+    use std::io::Read;
+
+    use crate::rarchive::ArchiveWriter;
+
+    let temp_dir = crate::util::TempDir::new("archive_index").unwrap();
+    let root = temp_dir.path();
+    let index_path = root.join("index.json");
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+
+    let write_archive = |path: &str, files: &[&str]| {
+        let mut writer = ArchiveWriter::new();
+        for file in files {
+            writer
+                .add_file(file, &DTIs::rTexture, 0, file.as_bytes())
+                .unwrap();
+        }
+
+        writer
+            .save(&mut File::create(root.join(path)).unwrap())
+            .unwrap();
+    };
+    let read_resource = |index: &ArchiveIndex, path: &str| {
+        let mut data = vec![];
+        index
+            .open_resource(&path.into(), DTIs::rTexture.hash())
+            .unwrap()
+            .map(|mut reader| reader.read_to_end(&mut data).unwrap())
+            .map(|_| data)
+    };
+
+    write_archive("a.arc", &["tex\\a", "tex\\shared"]);
+    write_archive("sub/b.arc", &["tex\\b", "tex\\shared"]);
+    // Can't be opened, and is skipped
+    std::fs::write(root.join("broken.arc"), b"not an archive").unwrap();
+
+    let index = ArchiveIndex::build(root, &index_path, None, None).unwrap();
+    assert_eq!(2, index.num_archives());
+    assert_eq!(3, index.num_resources());
+    assert_eq!(
+        Some(root.join("sub/b.arc")),
+        index.archive_path(&"tex\\b".into(), DTIs::rTexture.hash())
+    );
+    assert_eq!(
+        Some(root.join("a.arc")),
+        index.archive_path(&"tex\\shared".into(), DTIs::rTexture.hash())
+    );
+    assert_eq!(Some(b"tex\\b".to_vec()), read_resource(&index, "tex\\b"));
+
+    // A changed archive gets indexed again
+    write_archive("sub/b.arc", &["tex\\b", "tex\\new"]);
+
+    let index = ArchiveIndex::build(root, &index_path, None, None).unwrap();
+    assert_eq!(
+        Some(b"tex\\new".to_vec()),
+        read_resource(&index, "tex\\new")
+    );
+    assert_eq!(None, read_resource(&index, "tex\\missing"));
+}
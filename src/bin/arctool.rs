@@ -1,11 +1,89 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use mt_renderer::rarchive::cli_util::{repack_archive, unpack_archive};
+use anyhow::anyhow;
+use mt_renderer::{
+    hash_dictionary::HashDictionary,
+    rarchive::{
+        cli_util::{
+            diff_archive, edit_archive, list_archive, parse_dti_hash, repack_archive,
+            unpack_archive, verify_archive, ArchiveEdit,
+        },
+        ArchiveFormat, ArchiveKey,
+    },
+    DTI,
+};
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "usage: {} <command> <archive> ... [--key <key>] [--dti <dump>] \
+         [--arc-format <version>:<le|be>:<path_size>]",
+        program
+    );
+    eprintln!("commands:");
+    eprintln!("  unpack <archive>");
+    eprintln!("  pack <unpacked dir> <archive> [--recompress]");
+    eprintln!("  list <archive> [wordlist for unknown DTI hashes]");
+    eprintln!("  verify <archive>");
+    eprintln!("  diff <archive a> <archive b> [--format]");
+    eprintln!("  add <archive> <resource path> <dti> <file> [quality]");
+    eprintln!("  replace <archive> <resource path> <dti> <file>");
+    eprintln!("  remove <archive> <resource path> <dti>");
+    eprintln!("  rename <archive> <resource path> <dti> <new resource path>");
+    std::process::exit(1);
+}
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let args: Vec<_> = std::env::args().collect();
+    let mut args: Vec<_> = std::env::args().collect();
+
+    // "--key <key>" can be given anywhere, for encrypted archives
+    let key = match args.iter().position(|arg| arg == "--key") {
+        Some(key_idx) => {
+            if key_idx + 1 >= args.len() {
+                usage(&args[0]);
+            }
+
+            let key = args.remove(key_idx + 1);
+            args.remove(key_idx);
+
+            Some(ArchiveKey::new(key.as_bytes())?)
+        }
+        None => None,
+    };
+    let key = key.as_ref();
+
+    // "--arc-format <version>:<le|be>:<path_size>", for archives with a
+    // layout that isn't detected
+    let format = match args.iter().position(|arg| arg == "--arc-format") {
+        Some(format_idx) => {
+            if format_idx + 1 >= args.len() {
+                usage(&args[0]);
+            }
+
+            let format = args.remove(format_idx + 1);
+            args.remove(format_idx);
+
+            Some(format.parse::<ArchiveFormat>()?)
+        }
+        None => None,
+    };
+
+    // "--dti <dump>" loads the DTIs of another game, also anywhere
+    DTI::load_dump_from_args(&mut args)?;
+
+    // Including the program name
+    let min_args = match args.get(1).map(String::as_str) {
+        Some("unpack" | "list" | "verify") => 3,
+        Some("pack" | "diff") => 4,
+        Some("remove") => 5,
+        Some("add" | "replace" | "rename") => 6,
+        _ => usage(&args[0]),
+    };
+    if args.len() < min_args {
+        usage(&args[0]);
+    }
+
     let path = PathBuf::from(&args[2]);
 
     match args[1].as_str() {
@@ -13,13 +91,74 @@ fn main() -> anyhow::Result<()> {
             let out_dir = PathBuf::from(path.file_stem().unwrap());
             std::fs::create_dir(&out_dir)?;
 
-            unpack_archive(&path, &out_dir)
+            unpack_archive(&path, &out_dir, format, key)
         }
-        "pack" => repack_archive(&path),
+        "pack" => {
+            let out_path = PathBuf::from(&args[3]);
 
-        unknown => panic!("unhandled command: {}", unknown),
-    }
-    .unwrap();
+            // Recompress everything instead of copying unmodified files from
+            // the source archive
+            let recompress = args.get(4).is_some_and(|arg| arg == "--recompress");
 
-    Ok(())
+            repack_archive(&path, &out_path, !recompress, format, key)
+        }
+        "list" => {
+            let mut dictionary = HashDictionary::with_dti_names();
+            if let Some(wordlist_path) = args.get(3) {
+                dictionary.load_wordlist(Path::new(wordlist_path))?;
+            }
+
+            list_archive(&path, &dictionary, format, key)
+        }
+        "verify" => verify_archive(&path, format, key),
+        "diff" => {
+            let format_aware = args.get(4).is_some_and(|arg| arg == "--format");
+
+            diff_archive(&path, &PathBuf::from(&args[3]), format_aware, format, key)
+        }
+
+        // <archive> <resource path> <dti> ...
+        "add" => {
+            let data = std::fs::read(&args[5])?;
+            let quality = match args.get(6) {
+                Some(quality) => Some(
+                    quality
+                        .parse()
+                        .map_err(|_| anyhow!("invalid quality: {}", quality))?,
+                ),
+                None => None,
+            };
+            let edit = ArchiveEdit::Add {
+                path: &args[3],
+                quality,
+                data: &data,
+            };
+
+            edit_archive(&path, parse_dti_hash(&args[4])?, edit, format, key)
+        }
+        "replace" => {
+            let data = std::fs::read(&args[5])?;
+            let edit = ArchiveEdit::Replace {
+                path: &args[3],
+                data: &data,
+            };
+
+            edit_archive(&path, parse_dti_hash(&args[4])?, edit, format, key)
+        }
+        "remove" => {
+            let edit = ArchiveEdit::Remove { path: &args[3] };
+
+            edit_archive(&path, parse_dti_hash(&args[4])?, edit, format, key)
+        }
+        "rename" => {
+            let edit = ArchiveEdit::Rename {
+                path: &args[3],
+                new_path: &args[5],
+            };
+
+            edit_archive(&path, parse_dti_hash(&args[4])?, edit, format, key)
+        }
+
+        _ => unreachable!(),
+    }
 }
@@ -0,0 +1,86 @@
+use mt_renderer::DTI;
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "usage: {} <name substring|hash|file extension> [--dti <dump>]",
+        program
+    );
+    std::process::exit(1);
+}
+
+fn print_dti(dti: &DTI) {
+    println!("{}", dti.name());
+    println!("  hash: {:08x}", dti.hash());
+    println!("  size: {:#x}", dti.size());
+    if let Some(file_ext) = dti.file_ext() {
+        println!("  extension: {}", file_ext);
+    }
+
+    let ancestors: Vec<_> = dti.ancestors().map(|ancestor| ancestor.name()).collect();
+    if !ancestors.is_empty() {
+        println!("  parents: {}", ancestors.join(" -> "));
+    }
+
+    if !dti.props().is_empty() {
+        println!("  props:");
+    }
+    for prop in dti.props() {
+        let mut flags = vec![];
+        if prop.is_array() {
+            flags.push("array");
+        }
+        if prop.is_dynamic() {
+            flags.push("dynamic");
+        }
+
+        if flags.is_empty() {
+            println!("    {}: {:?}", prop.name(), prop.prop_type());
+        } else {
+            println!(
+                "    {}: {:?} ({})",
+                prop.name(),
+                prop.prop_type(),
+                flags.join(", ")
+            );
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args: Vec<_> = std::env::args().collect();
+
+    // "--dti <dump>" loads the DTIs of another game
+    DTI::load_dump_from_args(&mut args)?;
+
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
+
+    // Exact names also match the clean ones, like nGO__rCharacter
+    let exact = DTI::from_str(&args[1]);
+    let query = args[1].to_lowercase();
+    let hash = u32::from_str_radix(query.trim_start_matches("0x"), 16).ok();
+
+    let mut matches: Vec<_> = DTI::all()
+        .filter(|dti| {
+            Some(*dti) == exact
+                || Some(dti.hash()) == hash
+                || dti.file_ext() == Some(query.as_str())
+                || dti.name().to_lowercase().contains(&query)
+        })
+        .collect();
+    matches.sort_by_key(|dti| dti.name());
+
+    if matches.is_empty() {
+        eprintln!("no DTI matches {:?}", args[1]);
+        std::process::exit(1);
+    }
+
+    for dti in matches {
+        print_dti(dti);
+    }
+
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use mt_renderer::rguimessage::GuiMessageFile;
+use anyhow::anyhow;
+use mt_renderer::{
+    hash_dictionary::{HashDictionary, HashKind},
+    rguimessage::GuiMessageFile,
+};
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
@@ -19,6 +23,36 @@ fn main() -> anyhow::Result<()> {
             let mut out_file = std::fs::File::create("out.gmd")?;
             gmd.save(&mut out_file)?;
         }
+        "lookup" => {
+            // hash_a or hash_b -> label and message
+            let mut file = std::fs::File::open(&args[2])?;
+            let gmd = GuiMessageFile::new(&mut file)?;
+
+            let mut dictionary = HashDictionary::new();
+            dictionary.add_gmd(&gmd);
+
+            for hash in &args[3..] {
+                let hash = u32::from_str_radix(hash.trim_start_matches("0x"), 16)
+                    .map_err(|_| anyhow!("invalid hash {}", hash))?;
+
+                let labels: Vec<_> = [HashKind::gmd_a, HashKind::gmd_b]
+                    .iter()
+                    .flat_map(|kind| dictionary.resolve(*kind, hash))
+                    .collect();
+                if labels.is_empty() {
+                    println!("{:08x} ?", hash);
+                }
+
+                for label in labels {
+                    let message = gmd
+                        .messages()
+                        .iter()
+                        .find(|message| &message.label == label)
+                        .unwrap();
+                    println!("{:08x} {} {:?}", hash, label, message.message);
+                }
+            }
+        }
 
         unhandled => panic!("unhandled option: {unhandled}"),
     }
@@ -0,0 +1,91 @@
+use std::{fs::File, io::Read, path::Path, str::FromStr};
+
+use anyhow::anyhow;
+use mt_renderer::{
+    hash_dictionary::{HashDictionary, HashKind},
+    rarchive::ArchiveFile,
+};
+
+// Shorter runs are mostly noise
+const MIN_STRING_LEN: usize = 4;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!(
+            "usage: {} [--kind <kind>] [--words <wordlist>]... [--strings <file>]... \
+             [--archive <archive>]... <hash|->...",
+            args[0]
+        );
+        eprintln!(
+            "\"--archive\" adds resource paths, and names from GMD, XFS and rShader2 entries"
+        );
+        eprintln!("\"-\" reads whitespace separated hashes from stdin");
+        std::process::exit(1);
+    }
+
+    let mut dictionary = HashDictionary::with_dti_names();
+    let mut kind = None;
+    let mut hashes = vec![];
+
+    let mut args_iter = args[1..].iter();
+    while let Some(arg) = args_iter.next() {
+        let mut value = || {
+            args_iter
+                .next()
+                .ok_or_else(|| anyhow!("missing value for {}", arg))
+        };
+
+        match arg.as_str() {
+            "--kind" => {
+                let name = value()?;
+                kind = Some(HashKind::from_str(name).map_err(|_| anyhow!("unknown kind {}", name))?)
+            }
+            "--words" => {
+                dictionary.load_wordlist(Path::new(value()?))?;
+            }
+            "--strings" => {
+                let data = std::fs::read(value()?)?;
+                dictionary.add_strings(&data, MIN_STRING_LEN);
+            }
+            "--archive" => {
+                let archive = ArchiveFile::new(File::open(value()?)?)?;
+                dictionary.add_archive(&archive);
+                dictionary.add_archive_contents(&archive);
+            }
+            "-" => {
+                let mut input = String::new();
+                std::io::stdin().read_to_string(&mut input)?;
+                hashes.extend(input.split_whitespace().map(str::to_string));
+            }
+            hash => hashes.push(hash.to_string()),
+        }
+    }
+
+    eprintln!("{} candidate names", dictionary.len());
+
+    for hash in hashes {
+        let hash = u32::from_str_radix(hash.trim_start_matches("0x"), 16)
+            .map_err(|_| anyhow!("invalid hash {}", hash))?;
+
+        let names = match kind {
+            Some(kind) => dictionary
+                .resolve(kind, hash)
+                .iter()
+                .map(|name| (kind, name.as_str()))
+                .collect(),
+            None => dictionary.resolve_any(hash),
+        };
+
+        if names.is_empty() {
+            println!("{:08x} ?", hash);
+        }
+        for (kind, name) in names {
+            println!("{:08x} {} {}", hash, kind, name);
+        }
+    }
+
+    Ok(())
+}
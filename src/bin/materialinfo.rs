@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use mt_renderer::{
-    resource_manager::ResourceManager, rmaterial::MaterialFile, rshader2::Shader2File, DTIs,
+    hash_dictionary::{HashDictionary, HashKind},
+    resource_manager::ResourceManager,
+    rmaterial::MaterialFile,
+    rshader2::Shader2File,
+    DTIs,
 };
 
 fn main() -> anyhow::Result<()> {
@@ -10,16 +14,34 @@ fn main() -> anyhow::Result<()> {
     let args: Vec<_> = std::env::args().collect();
     let mut resource_manager = ResourceManager::new(&PathBuf::from(&args[1]));
 
-    let mut shader_file = resource_manager.get_resource(
-        &PathBuf::from("custom_shaders/CustomShaderPackage"),
-        &DTIs::rShader2,
-    )?;
-    let shader2 = Shader2File::new(&mut shader_file)?;
+    let shader2 =
+        resource_manager.load::<Shader2File>(Path::new("custom_shaders/CustomShaderPackage"))?;
+    let mut dictionary = HashDictionary::with_dti_names();
+    dictionary.add_shader2(&shader2);
+    // Optional wordlist with candidate material names
+    if let Some(wordlist_path) = args.get(3) {
+        dictionary.load_wordlist(Path::new(wordlist_path))?;
+    }
 
-    let mut file = resource_manager.get_resource_fancy(&args[2], &DTIs::rMaterial)?;
-    let material = MaterialFile::new(&mut file, &shader2)?;
+    resource_manager.register_loader(&DTIs::rMaterial, move |reader, _| {
+        MaterialFile::new(reader, &shader2)
+    });
+
+    // Either a path, or "<archive>:<path>"
+    let material_path = resource_manager.add_archive_from_path(&args[2])?;
+    let material = resource_manager.load::<MaterialFile>(Path::new(material_path))?;
 
     println!("{:#?}", material);
 
+    for material_info in material.materials() {
+        let name_hash = material_info.name_hash();
+        let names = dictionary.resolve(HashKind::crc32, name_hash);
+        println!(
+            "material {:08x}: {}",
+            name_hash,
+            names.first().map(String::as_str).unwrap_or("?")
+        );
+    }
+
     Ok(())
 }
@@ -1,6 +1,7 @@
-use std::{mem::size_of, path::PathBuf};
+use std::{collections::HashSet, mem::size_of, path::PathBuf, sync::mpsc::Receiver};
 
 use glam::Mat4;
+use log::{info, warn};
 use mt_renderer::{
     camera::Camera,
     debug_overlay::DebugOverlay,
@@ -8,7 +9,8 @@ use mt_renderer::{
     model::Model,
     mtserializer::{self, PropertyValue},
     renderer_app_manager::{RendererApp, RendererAppManager, RendererAppManagerPublic},
-    resource_manager::ResourceManager,
+    resource_manager::{ResourceChange, ResourceManager},
+    resource_path::ResourcePath,
     rmaterial::MaterialFile,
     rmodel::ModelFile,
     rshader2::Shader2File,
@@ -16,11 +18,47 @@ use mt_renderer::{
 };
 use zerocopy::AsBytes;
 
+fn load_model(
+    resource_manager: &ResourceManager,
+    shader2: &Shader2File,
+    model_path: &ResourcePath,
+    public: &RendererAppManagerPublic,
+    transform_bind_group_layout: &wgpu::BindGroupLayout,
+    swapchain_format: wgpu::TextureFormat,
+) -> anyhow::Result<Model> {
+    let mut model_resource = resource_manager.get_resource(model_path, &DTIs::rModel)?;
+    let model_file = ModelFile::new(&mut model_resource)?;
+
+    let mut material_resource = resource_manager.get_resource(model_path, &DTIs::rMaterial)?;
+
+    let material = MaterialFile::new(&mut material_resource, shader2)?;
+
+    Model::new(
+        &model_file,
+        &material,
+        shader2,
+        resource_manager,
+        public.device(),
+        public.queue(),
+        transform_bind_group_layout,
+        swapchain_format,
+    )
+}
+
 struct ModelViewerApp {
     model: Model,
     debug_overlay: DebugOverlay,
 
+    // For rebuilding the model when its files change
+    resource_manager: ResourceManager,
+    resource_changes: Receiver<ResourceChange>,
+    shader2: Shader2File,
+    model_path: ResourcePath,
+    parts_disp: Vec<bool>,
+    swapchain_format: wgpu::TextureFormat,
+
     transform_buf: wgpu::Buffer,
+    transform_bind_group_layout: wgpu::BindGroupLayout,
     transform_bind_group: wgpu::BindGroup,
 
     depth_texture: Option<wgpu::Texture>,
@@ -59,6 +97,48 @@ impl ModelViewerApp {
             self.depth_texture = Some(depth_texture);
         }
     }
+
+    fn handle_resource_changes(&mut self, public: &RendererAppManagerPublic) {
+        // Saving a file usually sends several changes
+        let changes: HashSet<_> = self
+            .resource_changes
+            .try_iter()
+            .map(|change| (change.path, change.dti.hash()))
+            .collect();
+
+        for (path, dti_hash) in changes {
+            if dti_hash == DTIs::rTexture.hash() {
+                match self.model.reload_texture(
+                    &path,
+                    &self.resource_manager,
+                    public.device(),
+                    public.queue(),
+                ) {
+                    Ok(true) => info!("reloaded texture {}", path),
+                    Ok(false) => {}
+                    Err(err) => warn!("couldn't reload texture {}: {:#}", path, err),
+                }
+            } else if (dti_hash == DTIs::rModel.hash() || dti_hash == DTIs::rMaterial.hash())
+                && path == self.model_path
+            {
+                match load_model(
+                    &self.resource_manager,
+                    &self.shader2,
+                    &self.model_path,
+                    public,
+                    &self.transform_bind_group_layout,
+                    self.swapchain_format,
+                ) {
+                    Ok(mut model) => {
+                        info!("reloaded model {}", path);
+                        model.set_parts_disp(&self.parts_disp);
+                        self.model = model;
+                    }
+                    Err(err) => warn!("couldn't reload model {}: {:#}", path, err),
+                }
+            }
+        }
+    }
 }
 
 impl RendererApp for ModelViewerApp {
@@ -74,6 +154,7 @@ impl RendererApp for ModelViewerApp {
         let args: Vec<_> = std::env::args().collect();
 
         let mut resource_manager = ResourceManager::new(&PathBuf::from(&args[1]));
+        let resource_changes = resource_manager.watch_loose_files()?;
 
         let mut shader_file = resource_manager
             .get_resource_fancy("custom_shaders/CustomShaderPackage", &DTIs::rShader2)?;
@@ -125,7 +206,7 @@ impl RendererApp for ModelViewerApp {
                 .values()[0],
             PropertyValue::Custom
         )[1]; // resource custom: (type, custom) TODO? handle customs properly
-        let model_path = PathBuf::from(model_path.replace("\\", "/"));
+        let model_path = ResourcePath::new(model_path);
 
         let parts_disp: Vec<bool> = character_info
             .get_prop("PartsDisp")
@@ -135,20 +216,11 @@ impl RendererApp for ModelViewerApp {
             .map(|val| *get_enum_value!(val, PropertyValue::Bool))
             .collect();
 
-        let mut model_resource = resource_manager.get_resource(&model_path, &DTIs::rModel)?;
-        let model_file = ModelFile::new(&mut model_resource)?;
-
-        let mut material_resource = resource_manager.get_resource(&model_path, &DTIs::rMaterial)?;
-
-        let material = MaterialFile::new(&mut material_resource, &shader2)?;
-
-        let mut model = Model::new(
-            &model_file,
-            &material,
-            &shader2,
+        let mut model = load_model(
             &resource_manager,
-            public.device(),
-            public.queue(),
+            &shader2,
+            &model_path,
+            public,
             &transform_bind_group_layout,
             swapchain_format,
         )?;
@@ -160,7 +232,15 @@ impl RendererApp for ModelViewerApp {
         Ok(ModelViewerApp {
             model,
 
+            resource_manager,
+            resource_changes,
+            shader2,
+            model_path,
+            parts_disp,
+            swapchain_format,
+
             transform_buf,
+            transform_bind_group_layout,
             transform_bind_group,
 
             depth_texture: None,
@@ -176,6 +256,8 @@ impl RendererApp for ModelViewerApp {
         frame_view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
     ) -> anyhow::Result<()> {
+        self.handle_resource_changes(manager);
+
         // FIXME: this should probably be handled by manager
         self.update_depth_texture(
             manager.device(),
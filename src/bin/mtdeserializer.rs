@@ -1,9 +1,24 @@
-use mt_renderer::mtserializer::{self, prp_file_to_mtserializer};
+use mt_renderer::{
+    mtserializer::{self, prp_file_to_mtserializer},
+    DTI,
+};
+
+fn usage(program: &str) -> ! {
+    eprintln!("usage: {} <file> [--dti <dump>]", program);
+    std::process::exit(1);
+}
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    let args: Vec<_> = std::env::args().collect();
+    let mut args: Vec<_> = std::env::args().collect();
+
+    // "--dti <dump>" loads the DTIs of another game
+    DTI::load_dump_from_args(&mut args)?;
+
+    if args.len() < 2 {
+        usage(&args[0]);
+    }
 
     let mut file = std::fs::File::open(&args[1])?;
 
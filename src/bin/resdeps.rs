@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use mt_renderer::{resource_deps::DependencyGraph, resource_manager::ResourceManager, DTI};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "usage: {} <base path> <[archive:]resource path> <dti name> [--dot]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let mut resource_manager = ResourceManager::new(&PathBuf::from(&args[1]));
+
+    // Either a path, or "<archive>:<path>"
+    let resource_path = resource_manager.add_archive_from_path(&args[2])?;
+    let dti = DTI::from_str(&args[3]).ok_or_else(|| anyhow!("unknown DTI {}", args[3]))?;
+
+    let graph = DependencyGraph::build(&resource_manager, resource_path, dti);
+
+    if args.get(4).map(String::as_str) == Some("--dot") {
+        print!("{}", graph.to_dot());
+    } else {
+        println!("{}", serde_json::to_string_pretty(&graph)?);
+    }
+
+    for node in graph.dangling() {
+        eprintln!("dangling reference: {:?} ({})", node.path, node.dti);
+    }
+    for node in graph.errors() {
+        eprintln!(
+            "couldn't read {:?} ({}): {}",
+            node.path,
+            node.dti,
+            node.error.as_deref().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
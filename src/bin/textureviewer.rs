@@ -45,7 +45,7 @@ impl RendererApp for TextureViewerApp {
             texture_resource.height() as f32,
         );
 
-        let texture = Texture::new(manager.device(), manager.queue(), texture_resource);
+        let texture = Texture::new(manager.device(), manager.queue(), &texture_resource);
 
         #[rustfmt::skip]
         let vertex_buf_data: [f32; 6 * 2] = [
@@ -1,6 +1,9 @@
 use std::{ffi::OsString, path::PathBuf};
 
-use mt_renderer::{rarchive::cli_util::unpack_archive, DTIs};
+use mt_renderer::{
+    rarchive::{cli_util::unpack_archive, ArchiveKey},
+    DTIs,
+};
 use walkdir::WalkDir;
 
 fn main() -> anyhow::Result<()> {
@@ -9,6 +12,11 @@ fn main() -> anyhow::Result<()> {
     let args: Vec<_> = std::env::args().collect();
 
     let game_root = PathBuf::from(&args[1]);
+    // Optional, for games with encrypted archives
+    let key = args
+        .get(2)
+        .map(|key| ArchiveKey::new(key.as_bytes()))
+        .transpose()?;
 
     let arc_extension = OsString::from(DTIs::rArchive.file_ext().unwrap());
     let walker = WalkDir::new(game_root).into_iter();
@@ -20,7 +28,6 @@ fn main() -> anyhow::Result<()> {
             continue;
         }
 
-
         let in_path = file.path().to_path_buf();
         let out_dir = in_path.with_file_name(in_path.file_stem().unwrap());
 
@@ -29,7 +36,7 @@ fn main() -> anyhow::Result<()> {
         assert!(!out_dir.exists());
         std::fs::create_dir(&out_dir)?;
 
-        unpack_archive(&in_path, &out_dir)?;
+        unpack_archive(&in_path, &out_dir, None, key.as_ref())?;
 
         std::fs::remove_file(in_path)?;
     }
@@ -1,7 +1,19 @@
 include!(concat!(env!("OUT_DIR"), "/dti_generated.rs"));
 
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
 #[allow(non_camel_case_types)]
-#[derive(strum::FromRepr, Debug, PartialEq)]
+#[derive(strum::FromRepr, strum::EnumString, Debug, PartialEq, Clone, Copy)]
 #[repr(u32)]
 pub enum PropType {
     undefined = 0,
@@ -97,11 +109,46 @@ impl From<u32> for PropType {
 pub const PROP_ATTR_ARRAY: u32 = 32;
 pub const PROP_ATTR_DYNAMIC: u32 = 128;
 
+/// A property in a DTI's schema
+#[derive(Debug)]
+pub struct DTIProp {
+    name: &'static str,
+    prop_type: PropType,
+    attr: u32,
+}
+
+impl DTIProp {
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn prop_type(&self) -> PropType {
+        self.prop_type
+    }
+
+    pub fn attr(&self) -> u32 {
+        self.attr
+    }
+
+    pub fn is_array(&self) -> bool {
+        (self.attr & PROP_ATTR_ARRAY) != 0
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        (self.attr & PROP_ATTR_DYNAMIC) != 0
+    }
+}
+
 #[derive(Debug)]
 pub struct DTI {
     name: &'static str,
     hash: u32,
+    // Size of an instance in the game, in bytes
+    size: u64,
     file_ext: Option<&'static str>,
+    // None for MtObject, which everything derives from
+    parent_hash: Option<u32>,
+    props: &'static [DTIProp],
 }
 
 impl PartialEq for DTI {
@@ -114,15 +161,175 @@ impl PartialEq for DTI {
     }
 }
 
+// A line of a DTI dump, in the same format as dti.txt (see build.rs)
+#[derive(Deserialize)]
+struct DumpPropEntry {
+    name: String,
+    prop_type: String,
+    attr: u32,
+}
+
+#[derive(Deserialize)]
+struct DumpEntry {
+    address: u64,
+    parent_address: u64,
+    name: String,
+    hash: u32,
+    size: u64,
+    file_extension: Option<String>,
+    #[serde(default)]
+    props: Vec<DumpPropEntry>,
+}
+
+#[derive(Default, Clone)]
+struct RuntimeDTIs {
+    by_hash: HashMap<u32, &'static DTI>,
+    // Real and clean names -> hash
+    by_name: HashMap<String, u32>,
+}
+
+// DTIs loaded with DTI::load_dump. They're never removed, and the maps are
+// replaced as a whole so that DTI::all can keep a copy while iterating.
+static RUNTIME_DTIS: RwLock<Option<Arc<RuntimeDTIs>>> = RwLock::new(None);
+
+// Same as the generated constant names, e.g. nGO__rCharacter (see build.rs)
+fn clean_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            ':' | '<' | '>' => '_',
+            _ => c,
+        })
+        .collect()
+}
+
 impl DTI {
+    /// Look up by name, either the real one (nGO::rCharacter) or the clean
+    /// one (nGO__rCharacter)
     pub fn from_str(name: &str) -> Option<&'static Self> {
-        generated::DTI_MAP.values().find(|d| d.name() == name)
+        let runtime_hash = RUNTIME_DTIS
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|dtis| dtis.by_name.get(name).copied());
+
+        let hash = runtime_hash.or_else(|| generated::DTI_NAMES.get(name).copied())?;
+        Self::from_hash(hash)
     }
 
     pub fn from_hash(hash: u32) -> Option<&'static Self> {
+        let runtime_dtis = RUNTIME_DTIS.read().unwrap();
+        if let Some(dti) = runtime_dtis
+            .as_ref()
+            .and_then(|dtis| dtis.by_hash.get(&hash))
+        {
+            return Some(dti);
+        }
+
         generated::DTI_MAP.get(&hash)
     }
 
+    /// Every known DTI, in no particular order
+    pub fn all() -> impl Iterator<Item = &'static Self> {
+        let runtime_dtis = RUNTIME_DTIS.read().unwrap().clone().unwrap_or_default();
+        let runtime_values: Vec<&'static Self> = runtime_dtis.by_hash.values().copied().collect();
+
+        runtime_values.into_iter().chain(
+            generated::DTI_MAP
+                .values()
+                .filter(move |dti| !runtime_dtis.by_hash.contains_key(&dti.hash)),
+        )
+    }
+
+    /// Load DTIs from a dump in the same format as dti.txt, e.g. for another
+    /// MT Framework game. They're used by every lookup from then on (so by
+    /// archives, mtserializer and materials too), and replace the built-in
+    /// DTIs with the same hash. Returns how many DTIs were loaded.
+    pub fn load_dump(path: &Path) -> anyhow::Result<usize> {
+        Self::load_dump_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Handle a "--dti <dump>" option for command line tools. It can be
+    /// anywhere in args, and is removed from them so that the other arguments
+    /// can be parsed by position. Returns how many DTIs were loaded, if the
+    /// option was given.
+    pub fn load_dump_from_args(args: &mut Vec<String>) -> anyhow::Result<Option<usize>> {
+        let Some(dti_idx) = args.iter().position(|arg| arg == "--dti") else {
+            return Ok(None);
+        };
+
+        if dti_idx + 1 >= args.len() {
+            return Err(anyhow!("--dti needs the path to a DTI dump"));
+        }
+
+        let dump_path = args.remove(dti_idx + 1);
+        args.remove(dti_idx);
+
+        Ok(Some(Self::load_dump(Path::new(&dump_path))?))
+    }
+
+    pub fn load_dump_from<R: BufRead>(reader: R) -> anyhow::Result<usize> {
+        let mut entries = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str::<DumpEntry>(&line)?);
+            }
+        }
+
+        // Parents are referenced by address
+        let hashes_by_address: HashMap<u64, u32> = entries
+            .iter()
+            .map(|entry| (entry.address, entry.hash))
+            .collect();
+
+        let mut dtis: RuntimeDTIs = RUNTIME_DTIS
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|dtis| dtis.as_ref().clone())
+            .unwrap_or_default();
+
+        // Like build.rs, the first of any duplicated entries wins
+        let mut handled_entries = HashSet::new();
+        for entry in entries {
+            if !handled_entries.insert(entry.hash) {
+                continue;
+            }
+
+            let props = entry
+                .props
+                .into_iter()
+                .map(|prop| {
+                    let prop_type = PropType::from_str(&prop.prop_type).map_err(|_| {
+                        anyhow!("unknown prop type {:?} in {}", prop.prop_type, entry.name)
+                    })?;
+
+                    Ok(DTIProp {
+                        name: prop.name.leak(),
+                        prop_type,
+                        attr: prop.attr,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let dti = Box::leak(Box::new(DTI {
+                name: entry.name.leak(),
+                hash: entry.hash,
+                size: entry.size,
+                file_ext: entry.file_extension.map(|file_ext| &*file_ext.leak()),
+                parent_hash: hashes_by_address.get(&entry.parent_address).copied(),
+                props: props.leak(),
+            }));
+            dtis.by_hash.insert(dti.hash, dti);
+            dtis.by_name.insert(dti.name.to_string(), dti.hash);
+            dtis.by_name.entry(clean_name(dti.name)).or_insert(dti.hash);
+        }
+
+        *RUNTIME_DTIS.write().unwrap() = Some(Arc::new(dtis));
+
+        Ok(handled_entries.len())
+    }
+
     pub fn name(&self) -> &str {
         self.name
     }
@@ -131,17 +338,43 @@ impl DTI {
         self.hash
     }
 
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
     pub fn file_ext(&self) -> Option<&str> {
         self.file_ext
     }
 
-    pub fn is_type_of(&self, dti: &DTI) -> bool {
-        if self == dti {
-            return true;
-        };
+    /// Every property, including inherited ones, in the order the game lists
+    /// them
+    pub fn props(&self) -> &[DTIProp] {
+        self.props
+    }
+
+    pub fn prop(&self, name: &str) -> Option<&DTIProp> {
+        self.props.iter().find(|prop| prop.name == name)
+    }
+
+    /// The class this one derives from
+    pub fn parent(&self) -> Option<&'static Self> {
+        Self::from_hash(self.parent_hash?)
+    }
+
+    /// Parent, grandparent, etc. up to MtObject
+    pub fn ancestors(&self) -> impl Iterator<Item = &'static Self> {
+        std::iter::successors(self.parent(), |dti| dti.parent())
+    }
+
+    /// Classes that derive directly from this one, in no particular order
+    pub fn children(&self) -> impl Iterator<Item = &'static Self> + '_ {
+        Self::all().filter(|dti| dti.parent_hash == Some(self.hash))
+    }
 
-        // need to implement parent stuff in build script
-        todo!("check parents")
+    /// Whether this is dti or derives from it, e.g. every nDraw::Material*
+    /// is a type of nDraw::Material
+    pub fn is_type_of(&self, dti: &DTI) -> bool {
+        self == dti || self.ancestors().any(|ancestor| ancestor == dti)
     }
 }
 
@@ -165,6 +398,126 @@ fn test_dti_eq() {
     assert_eq!(generated::rArchive, generated::rArchive);
 }
 
+#[test]
+fn test_from_str() {
+    assert_eq!(
+        Some(&generated::nGO__rCharacter),
+        DTI::from_str("nGO::rCharacter")
+    );
+    assert_eq!(
+        Some(&generated::nGO__rCharacter),
+        DTI::from_str("nGO__rCharacter")
+    );
+    assert_eq!(Some(&generated::rTexture), DTI::from_str("rTexture"));
+    assert_eq!(None, DTI::from_str("nGO::rMissing"));
+
+    // Every DTI can be found by name
+    for dti in DTI::all() {
+        assert_eq!(Some(dti), DTI::from_str(dti.name()));
+    }
+}
+
+#[test]
+fn test_dti_hierarchy() {
+    use generated::*;
+
+    assert_eq!(Some(&cResource), rTexture.parent());
+    assert_eq!(None, MtObject.parent());
+    assert_eq!(
+        vec!["cResource", "MtObject"],
+        rTexture
+            .ancestors()
+            .map(|dti| dti.name())
+            .collect::<Vec<_>>()
+    );
+    assert!(cResource.children().any(|dti| *dti == rTexture));
+
+    assert!(nDraw__MaterialToon.is_type_of(&nDraw__Material));
+    assert!(rTexture.is_type_of(&MtObject));
+    assert!(rTexture.is_type_of(&rTexture));
+    assert!(!cResource.is_type_of(&rTexture));
+}
+
+#[test]
+fn test_dti_props() {
+    let character = &generated::nGO__rCharacter;
+
+    assert_eq!("mpModel", character.props()[0].name());
+    // Inherited from cResource
+    assert!(character.prop("mPath").is_some());
+
+    let model = character.prop("mpModel").unwrap();
+    assert_eq!(PropType::custom, model.prop_type());
+    assert!(model.is_dynamic());
+    assert!(!model.is_array());
+
+    let parts_disp = character.prop("PartsDisp").unwrap();
+    assert_eq!(PropType::bool, parts_disp.prop_type());
+    assert!(parts_disp.is_array());
+
+    assert!(character.prop("mMissing").is_none());
+}
+
+#[test]
+fn test_load_dump() {
+    use crate::util::crc32;
+
+    // Made up classes, so that other tests don't see any difference
+    let hash = |name: &str| crc32(name.as_bytes(), u32::MAX) & 0x7fffffff;
+    let dump = format!(
+        "{}\n{}\n",
+        serde_json::json!({
+            "address": 1, "parent_address": 0, "name": "nTest::rBase",
+            "hash": hash("nTest::rBase"), "size": 8, "file_extension": null, "props": [],
+        }),
+        serde_json::json!({
+            "address": 2, "parent_address": 1, "name": "nTest::rDerived",
+            "hash": hash("nTest::rDerived"), "size": 16, "file_extension": "tst",
+            "props": [{"name": "mValue", "prop_type": "u32", "attr": 0}],
+        }),
+    );
+
+    assert_eq!(2, DTI::load_dump_from(dump.as_bytes()).unwrap());
+
+    let derived = DTI::from_hash(hash("nTest::rDerived")).unwrap();
+    assert_eq!("nTest::rDerived", derived.name());
+    assert_eq!(Some("nTest::rBase"), derived.parent().map(|dti| dti.name()));
+    assert_eq!(PropType::u32, derived.prop("mValue").unwrap().prop_type());
+    assert_eq!(16, derived.size());
+    assert_eq!(Some(derived), DTI::from_str("nTest::rDerived"));
+    assert_eq!(Some(derived), DTI::from_str("nTest__rDerived"));
+    assert!(DTI::all().any(|dti| dti == derived));
+
+    // Everything that looks up DTIs sees them
+    assert_eq!(
+        "tst",
+        crate::rarchive::file_ext_for_dti_hash(hash("nTest::rDerived"))
+    );
+
+    // Built-in DTIs are still there
+    assert_eq!(Some(&generated::rTexture), DTI::from_str("rTexture"));
+}
+
+#[test]
+fn test_load_dump_from_args() {
+    let mut args: Vec<String> = vec!["tool".into(), "file".into()];
+    assert_eq!(None, DTI::load_dump_from_args(&mut args).unwrap());
+    assert_eq!(2, args.len());
+
+    let mut args: Vec<String> = vec!["tool".into(), "file".into(), "--dti".into()];
+    assert!(DTI::load_dump_from_args(&mut args).is_err());
+
+    // Removed even if the dump can't be loaded
+    let mut args: Vec<String> = vec![
+        "tool".into(),
+        "--dti".into(),
+        "does/not/exist.txt".into(),
+        "file".into(),
+    ];
+    assert!(DTI::load_dump_from_args(&mut args).is_err());
+    assert_eq!(vec!["tool", "file"], args);
+}
+
 #[test]
 fn test_dti_hashes() {
     use crate::util::crc32;
@@ -0,0 +1,410 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Seek},
+    path::Path,
+};
+
+use log::warn;
+
+use crate::{
+    mtserializer::{self, prp_file_to_mtserializer, Class, PropertyValue},
+    rarchive::ArchiveFile,
+    rguimessage::GuiMessageFile,
+    rshader2::Shader2File,
+    util, DTI,
+};
+
+/// The ways names are turned into hashes with util::crc32
+#[allow(non_camel_case_types)]
+#[derive(strum::EnumString, strum::Display, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashKind {
+    /// Plain crc32, e.g. material name hashes
+    crc32,
+    /// Lower 31 bits, DTI hashes
+    dti,
+    /// Lower 20 bits, shader objects. Handles keep it in bits 12-31.
+    shader_object,
+    /// GMD hash_a, seeded with the crc32 of the name
+    gmd_a,
+    /// GMD hash_b, seeded with hash_a
+    gmd_b,
+}
+
+impl HashKind {
+    pub const ALL: [Self; 5] = [
+        Self::crc32,
+        Self::dti,
+        Self::shader_object,
+        Self::gmd_a,
+        Self::gmd_b,
+    ];
+
+    pub fn hash(&self, name: &str) -> u32 {
+        let hash = util::crc32(name.as_bytes(), 0xffff_ffff);
+
+        match self {
+            Self::crc32 => hash,
+            Self::dti => hash & 0x7fffffff,
+            Self::shader_object => hash & 0xfffff,
+            Self::gmd_a => util::crc32(name.as_bytes(), hash),
+            Self::gmd_b => {
+                let hash_a = util::crc32(name.as_bytes(), hash);
+                util::crc32(name.as_bytes(), hash_a)
+            }
+        }
+    }
+}
+
+/// Candidate names, indexed by every kind of hash so that hashes found in
+/// files can be turned back into names
+#[derive(Default)]
+pub struct HashDictionary {
+    names: HashSet<String>,
+    // Several names can have the same hash, these are in insertion order
+    hashes: HashMap<(HashKind, u32), Vec<String>>,
+}
+
+impl HashDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts out with every DTI name
+    pub fn with_dti_names() -> Self {
+        let mut dictionary = Self::new();
+        for dti in DTI::all() {
+            dictionary.add(dti.name());
+        }
+
+        dictionary
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Returns false if the name was already known
+    pub fn add(&mut self, name: &str) -> bool {
+        // crc32 stops at nul, so those names can never match
+        if name.is_empty() || name.contains('\0') || !self.names.insert(name.to_string()) {
+            return false;
+        }
+
+        for kind in HashKind::ALL {
+            self.hashes
+                .entry((kind, kind.hash(name)))
+                .or_default()
+                .push(name.to_string());
+        }
+
+        true
+    }
+
+    /// One name per line, empty lines and lines starting with '#' are
+    /// skipped. Returns how many new names were added.
+    pub fn add_wordlist<R: BufRead>(&mut self, reader: R) -> anyhow::Result<usize> {
+        let mut added = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let name = line.trim();
+            if name.starts_with('#') {
+                continue;
+            }
+
+            if self.add(name) {
+                added += 1;
+            }
+        }
+
+        Ok(added)
+    }
+
+    pub fn load_wordlist(&mut self, path: &Path) -> anyhow::Result<usize> {
+        self.add_wordlist(BufReader::new(File::open(path)?))
+    }
+
+    /// Every printable ASCII run of at least min_len bytes, like strings(1).
+    /// Works on any file, parsed or not.
+    pub fn add_strings(&mut self, data: &[u8], min_len: usize) -> usize {
+        data.split(|b| !(b.is_ascii_graphic() || *b == b' '))
+            .filter(|run| run.len() >= min_len)
+            .filter(|run| self.add(std::str::from_utf8(run).unwrap()))
+            .count()
+    }
+
+    pub fn add_shader2(&mut self, shader2: &Shader2File) {
+        for object in shader2.objects() {
+            self.add(object.name());
+        }
+    }
+
+    /// Property names, strings and custom values, recursively
+    pub fn add_class(&mut self, class: &Class) {
+        self.add(class.class_type().name());
+
+        for (name, prop) in class.props() {
+            self.add(name);
+
+            for value in prop.values() {
+                match value {
+                    PropertyValue::Class(Some(class)) => self.add_class(class),
+                    PropertyValue::String(string) => {
+                        self.add(string);
+                    }
+                    PropertyValue::Custom(values) => {
+                        for value in values {
+                            self.add(value);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn add_gmd(&mut self, gmd: &GuiMessageFile) {
+        for message in gmd.messages() {
+            self.add(&message.label);
+        }
+    }
+
+    /// Resource paths, which are also what some file names are hashed from
+    pub fn add_archive<R: Read + Seek>(&mut self, archive: &ArchiveFile<R>) {
+        for info in archive.resource_infos() {
+            self.add(info.path());
+        }
+    }
+
+    /// Names from a resource in any format that has an add_* function,
+    /// detected from its magic. Returns false for other formats.
+    pub fn add_resource(&mut self, data: &[u8]) -> anyhow::Result<bool> {
+        match data.get(..4) {
+            Some(b"GMD\0") => self.add_gmd(&GuiMessageFile::new(&mut Cursor::new(data))?),
+            Some(b"XFS\0") | Some(b"PRPZ") => {
+                let mut cursor = prp_file_to_mtserializer(&mut Cursor::new(data))?;
+                self.add_class(&mtserializer::deserialize(&mut cursor)?);
+            }
+            Some(b"MFX\0") => self.add_shader2(&Shader2File::new(&mut Cursor::new(data))?),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Names from every GMD, XFS and rShader2 entry in an archive. Entries
+    /// that can't be parsed are skipped. Returns how many entries were used.
+    pub fn add_archive_contents<R: Read + Seek>(&mut self, archive: &ArchiveFile<R>) -> usize {
+        let mut num_used = 0;
+        for info in archive.resource_infos() {
+            let added = archive
+                .get_resource_by_info(info)
+                .and_then(|data| self.add_resource(&data.unwrap()));
+
+            match added {
+                Ok(true) => num_used += 1,
+                Ok(false) => {}
+                Err(err) => warn!(
+                    "skipping {:?} ({}): {:#}",
+                    info.path(),
+                    info.dti_name(),
+                    err
+                ),
+            }
+        }
+
+        num_used
+    }
+
+    /// Every known name with this hash
+    pub fn resolve(&self, kind: HashKind, hash: u32) -> &[String] {
+        self.hashes
+            .get(&(kind, hash))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Names with this hash for any kind of hash, as (kind, name)
+    pub fn resolve_any(&self, hash: u32) -> Vec<(HashKind, &str)> {
+        HashKind::ALL
+            .iter()
+            .flat_map(|kind| {
+                self.resolve(*kind, hash)
+                    .iter()
+                    .map(|name| (*kind, name.as_str()))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_hash_dictionary() {
+    let mut dictionary = HashDictionary::with_dti_names();
+    assert_eq!(
+        vec!["rTexture"],
+        dictionary.resolve(HashKind::dti, crate::DTIs::rTexture.hash())
+    );
+
+    let words = "# comment\nmat_body\n\nmat_body\nmat_face\n";
+    assert_eq!(2, dictionary.add_wordlist(words.as_bytes()).unwrap());
+
+    // Same as MaterialFile::material_by_name
+    let body_hash = util::crc32(b"mat_body", 0xffff_ffff);
+    assert_eq!(
+        vec!["mat_body"],
+        dictionary.resolve(HashKind::crc32, body_hash)
+    );
+    assert!(dictionary
+        .resolve_any(body_hash)
+        .contains(&(HashKind::crc32, "mat_body")));
+    assert_eq!(
+        vec!["mat_face"],
+        dictionary.resolve(
+            HashKind::shader_object,
+            HashKind::shader_object.hash("mat_face")
+        )
+    );
+    assert!(dictionary.resolve(HashKind::crc32, 0).is_empty());
+
+    assert_eq!(
+        2,
+        dictionary.add_strings(b"\x01\x02tex\\pl00\0ab\0mat_body\0FOG_COLOR\xff", 4)
+    );
+    assert_eq!(
+        vec!["tex\\pl00"],
+        dictionary.resolve(HashKind::gmd_b, HashKind::gmd_b.hash("tex\\pl00"))
+    );
+}
+
+#[test]
+fn test_add_class() {
+    use crate::{dti::PropType, mtserializer::Property};
+
+    // Classes of the same type need the same properties
+    let mut child = Class::new(&crate::DTIs::rTexture);
+    child.props_mut().push((
+        "mChildName".to_string(),
+        Property::new(
+            PropType::string,
+            0,
+            0,
+            vec![PropertyValue::String("pl00_child".to_string())],
+        ),
+    ));
+
+    let mut class = Class::new(&crate::DTIs::MtObject);
+    class.props_mut().extend([
+        (
+            "mpChild".to_string(),
+            Property::new(
+                PropType::class,
+                0,
+                8,
+                vec![PropertyValue::Class(Some(child))],
+            ),
+        ),
+        (
+            "mModel".to_string(),
+            Property::new(
+                PropType::custom,
+                0,
+                0,
+                vec![PropertyValue::Custom(vec![
+                    "rModel".to_string(),
+                    "model\\pl00".to_string(),
+                ])],
+            ),
+        ),
+        (
+            "mValue".to_string(),
+            Property::new(PropType::u32, 0, 4, vec![PropertyValue::U32(1)]),
+        ),
+    ]);
+
+    let mut dictionary = HashDictionary::new();
+    dictionary.add_class(&class);
+
+    for name in [
+        "MtObject",
+        "rTexture",
+        "mpChild",
+        "mChildName",
+        "pl00_child",
+        "mModel",
+        "rModel",
+        "model\\pl00",
+        "mValue",
+    ] {
+        assert_eq!(
+            vec![name],
+            dictionary.resolve(HashKind::crc32, HashKind::crc32.hash(name))
+        );
+    }
+    assert_eq!(9, dictionary.len());
+
+    // The same names, found through the serialized file
+    let mut data = vec![];
+    mtserializer::serialize(&class, &mut data).unwrap();
+
+    let mut from_file = HashDictionary::new();
+    assert!(from_file.add_resource(&data).unwrap());
+    assert_eq!(9, from_file.len());
+}
+
+#[test]
+fn test_add_gmd() {
+    use crate::rarchive::ArchiveWriter;
+
+    let gmd: GuiMessageFile = serde_json::from_str(
+        r#"{
+            "update_time": "2015-07-09T00:00:00Z",
+            "language_id": 1,
+            "package_name": "msg_test",
+            "messages": [
+                { "label": "MSG_TITLE", "message": "Title" },
+                { "label": "MSG_QUIT", "message": "Quit" }
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let mut dictionary = HashDictionary::new();
+    dictionary.add_gmd(&gmd);
+    for label in ["MSG_TITLE", "MSG_QUIT"] {
+        assert_eq!(
+            vec![label],
+            dictionary.resolve(HashKind::gmd_a, HashKind::gmd_a.hash(label))
+        );
+        assert_eq!(
+            vec![label],
+            dictionary.resolve(HashKind::gmd_b, HashKind::gmd_b.hash(label))
+        );
+    }
+
+    // Through an archive, next to an entry that isn't parsed
+    let mut gmd_data = vec![];
+    gmd.save(&mut gmd_data).unwrap();
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("msg\\test", &crate::DTIs::rGUIMessage, 0, &gmd_data)
+        .unwrap();
+    writer
+        .add_file("tex\\test", &crate::DTIs::rTexture, 0, b"not parsed")
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+
+    let mut from_archive = HashDictionary::new();
+    assert_eq!(1, from_archive.add_archive_contents(&archive));
+    assert_eq!(
+        vec!["MSG_QUIT"],
+        from_archive.resolve(HashKind::gmd_b, HashKind::gmd_b.hash("MSG_QUIT"))
+    );
+}
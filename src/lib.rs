@@ -5,6 +5,13 @@ pub use dti::DTI;
 pub mod input_state;
 pub mod renderer_app_manager;
 pub mod resource_manager;
+pub mod resource_path;
+pub mod resource_source;
+
+pub mod archive_diff;
+pub mod archive_index;
+pub mod hash_dictionary;
+pub mod resource_deps;
 
 pub mod mtserializer;
 pub mod rarchive;
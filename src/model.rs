@@ -1,4 +1,4 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, sync::Arc};
 
 use log::{debug, trace};
 use wgpu::util::DeviceExt;
@@ -7,12 +7,12 @@ use zerocopy::AsBytes;
 use crate::{
     debug_overlay::DebugOverlay,
     resource_manager::ResourceManager,
+    resource_path::ResourcePath,
     rmaterial::MaterialFile,
     rmodel::ModelFile,
     rshader2::{Shader2File, Shader2ObjectTypedInfo},
     rtexture::TextureFile,
     texture::Texture,
-    DTIs,
 };
 
 pub struct Model {
@@ -25,7 +25,9 @@ pub struct Model {
     pipelines: HashMap<(u32, u32, u32), wgpu::RenderPipeline>,
 
     primitives: Vec<crate::rmodel::PrimitiveInfo>,
-    textures: Vec<Option<Texture>>,
+    textures: Vec<Option<Arc<Texture>>>,
+    // Same order as textures
+    texture_paths: Vec<ResourcePath>,
     mat_to_tex: Vec<Option<usize>>,
     parts_disp: Vec<bool>,
 
@@ -43,17 +45,21 @@ impl Model {
         transform_bind_group_layout: &wgpu::BindGroupLayout,
         swapchain_format: wgpu::TextureFormat,
     ) -> anyhow::Result<Self> {
+        // Materials can share textures, each one is only uploaded once
+        let mut gpu_textures: HashMap<ResourcePath, Option<Arc<Texture>>> = HashMap::new();
         let textures: Vec<_> = material_file
             .textures()
             .iter()
             .map(|path| {
-                trace!("Loading texture {:?}", path);
-                let mut file = resource_manager
-                    .get_resource(&PathBuf::from(&path.replace('\\', "/")), &DTIs::rTexture)
-                    .ok()?;
-                let texture = TextureFile::new(&mut file).ok()?;
-
-                Some(Texture::new(device, queue, texture))
+                gpu_textures
+                    .entry(ResourcePath::from(path))
+                    .or_insert_with(|| {
+                        trace!("Loading texture {:?}", path);
+                        let texture = resource_manager.load::<TextureFile>(path).ok()?;
+
+                        Some(Arc::new(Texture::new(device, queue, &texture)))
+                    })
+                    .clone()
             })
             .collect();
 
@@ -278,6 +284,11 @@ impl Model {
             debug_ids,
             primitives,
             textures,
+            texture_paths: material_file
+                .textures()
+                .iter()
+                .map(ResourcePath::from)
+                .collect(),
             mat_to_tex,
             parts_disp,
             joint_positions: joint_info
@@ -292,6 +303,32 @@ impl Model {
         })
     }
 
+    /// Load a texture again, e.g. after it was changed on disk. Returns
+    /// whether the model uses it.
+    pub fn reload_texture(
+        &mut self,
+        path: &ResourcePath,
+        resource_manager: &ResourceManager,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<bool> {
+        let mut reloaded: Option<Arc<Texture>> = None;
+
+        for (texture, texture_path) in self.textures.iter_mut().zip(&self.texture_paths) {
+            if texture_path != path {
+                continue;
+            }
+
+            if reloaded.is_none() {
+                let texture_file = resource_manager.load::<TextureFile>(path)?;
+                reloaded = Some(Arc::new(Texture::new(device, queue, &texture_file)));
+            }
+            texture.clone_from(&reloaded);
+        }
+
+        Ok(reloaded.is_some())
+    }
+
     pub fn set_parts_disp(&mut self, parts_disp: &[bool]) {
         self.parts_disp = parts_disp.to_vec()
     }
@@ -1,11 +1,13 @@
 use std::{
+    collections::HashMap,
     ffi::CStr,
-    io::{Read, Seek},
+    io::{Read, Seek, SeekFrom, Write},
     mem::size_of,
 };
 
+use anyhow::{anyhow, bail};
 use log::{debug, warn};
-use zerocopy::{FromBytes, FromZeroes};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 use crate::{
     dti::{self, PropType},
@@ -13,8 +15,18 @@ use crate::{
     DTI,
 };
 
+const XFS_MAGIC: &[u8; 4] = b"XFS\0";
+const XFS_MAJOR_VERSION: u16 = 16;
+
+// PRPZ files are XFS files behind a 12 byte header
+const PRP_MAGIC: &[u8; 4] = b"PRPZ";
+const PRP_HEADER_SIZE: usize = 12;
+
+// Object index 0x7fff
+const NULL_CLASS_INFO: u32 = 0xfffe;
+
 #[repr(C, packed)]
-#[derive(FromBytes, FromZeroes, Debug)]
+#[derive(FromBytes, FromZeroes, AsBytes, Debug)]
 struct Header {
     magic: u32,
     major_version: u16,
@@ -28,7 +40,7 @@ struct Header {
 }
 
 #[repr(C, packed)]
-#[derive(FromBytes, FromZeroes, Debug)]
+#[derive(FromBytes, FromZeroes, AsBytes, Debug)]
 struct RawObjectInfo {
     dti_hash: u32,
     padding_0x4: u32, // original: union { hash: u32, dti: MtDTI* }
@@ -39,7 +51,7 @@ struct RawObjectInfo {
 }
 
 #[repr(C, packed)]
-#[derive(FromBytes, FromZeroes, Debug)]
+#[derive(FromBytes, FromZeroes, AsBytes, Debug)]
 struct RawPropertyInfo {
     name: u64, // char*
 
@@ -60,6 +72,8 @@ struct ObjectInfo {
 #[derive(Debug)]
 struct PropertyInfo {
     name: String,
+    // As in the file, the rest is decoded from it
+    bitfield: u32,
     prop_raw_type: u32,
     prop_attr: u32,
     prop_size: u32,
@@ -69,20 +83,59 @@ struct PropertyInfo {
     is_disabled: bool,
 }
 
-#[derive(Debug)]
-pub struct Property(Vec<PropertyValue>);
+#[derive(Debug, PartialEq)]
+pub struct Property {
+    // type: 8, attr: 8, size: 15, disabled: 1, like RawPropertyInfo
+    bitfield: u32,
+    values: Vec<PropertyValue>,
+}
+
 impl Property {
+    pub fn new(prop_type: PropType, attr: u32, size: u32, values: Vec<PropertyValue>) -> Self {
+        Self {
+            bitfield: (prop_type as u32 & 0xff) | ((attr & 0xff) << 8) | ((size & 0x7fff) << 16),
+            values,
+        }
+    }
+
     pub fn values(&self) -> &[PropertyValue] {
-        &self.0
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut Vec<PropertyValue> {
+        &mut self.values
+    }
+
+    pub fn prop_type(&self) -> PropType {
+        PropType::from(self.bitfield & 0xff)
+    }
+
+    pub fn attr(&self) -> u32 {
+        (self.bitfield >> 8) & 0xff
+    }
+
+    pub fn size(&self) -> u32 {
+        (self.bitfield >> 16) & 0x7fff
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        (self.attr() & dti::PROP_ATTR_DYNAMIC) != 0
     }
 }
 
-#[derive(Debug)]
+/// vector3s are padded to 16 bytes: x, y, z, padding. The padding isn't
+/// always 0, so it's kept.
+pub type PaddedVector3 = [f32; 4];
+
+#[derive(Debug, PartialEq)]
 pub enum PropertyValue {
     Class(Option<Class>),
     U16(u16),
     Custom(Vec<String>),
-    Vector3(f32, f32, f32),
+    // x, y, z, padding
+    Vector3(f32, f32, f32, f32),
+    // bool properties with a byte other than 0 or 1 are read as U8, so that
+    // it's written back as is
     Bool(bool),
     U8(u8),
     F32(f32),
@@ -91,15 +144,109 @@ pub enum PropertyValue {
     S16(i16),
     S8(i8),
     String(String),
+    U64(u64),
+    S64(i64),
+    F64(f64),
+    // r, g, b, a
+    Color(u8, u8, u8, u8),
+    Point(i32, i32),
+    Size(i32, i32),
+    // left, top, right, bottom
+    Rect(i32, i32, i32, i32),
+    Matrix44([[f32; 4]; 4]),
+    Vector2(f32, f32),
+    Vector4(f32, f32, f32, f32),
+    Quaternion(f32, f32, f32, f32),
+    Matrix33([PaddedVector3; 3]),
+    Event32(u32),
+    Event64(u64),
+    Time(u64),
+    Float2(f32, f32),
+    Float3(f32, f32, f32),
+    Float4(f32, f32, f32, f32),
+    Float3x3([[f32; 3]; 3]),
+    Float4x3([[f32; 3]; 4]),
+    Float3x4([[f32; 4]; 3]),
+    Float4x4([[f32; 4]; 4]),
+    // p1, p2
+    EaseCurve(f32, f32),
+    // x, y
+    HermiteCurve([f32; 8], [f32; 8]),
+    // min, max
+    Range(i32, i32),
+    RangeF(f32, f32),
+    RangeU16(u16, u16),
+    PointF(f32, f32),
+    SizeF(f32, f32),
+    RectF(f32, f32, f32, f32),
+
+    // Shapes
+    // from, direction
+    Line(PaddedVector3, PaddedVector3),
+    LineSegment(PaddedVector3, PaddedVector3),
+    // from, direction
+    Ray(PaddedVector3, PaddedVector3),
+    // normal, distance
+    Plane([f32; 3], f32),
+    // position, radius
+    Sphere([f32; 3], f32),
+    // p0, p1, radius, padding (guessed)
+    Capsule(PaddedVector3, PaddedVector3, f32, [f32; 3]),
+    // min, max
+    Aabb(PaddedVector3, PaddedVector3),
+    // transform, extent
+    Obb([[f32; 4]; 4], PaddedVector3),
+    // p0, p1, radius, padding (guessed)
+    Cylinder(PaddedVector3, PaddedVector3, f32, [f32; 3]),
+    Triangle([PaddedVector3; 3]),
+    // p0, r0, p1, r1 (guessed)
+    Cone([f32; 3], f32, [f32; 3], f32),
+    // position, axis, radius, cross section radius, padding (guessed)
+    Torus(PaddedVector3, PaddedVector3, f32, f32, [f32; 2]),
+    // position, radii
+    Ellipsoid(PaddedVector3, PaddedVector3),
+    LineSegment4([f32; 4], [f32; 4]),
+    Aabb4([f32; 4], [f32; 4]),
+
+    // Types with an unknown layout, the property size in bytes as is
+    Raw(Vec<u8>),
 }
 
 #[derive(Debug)]
 pub struct Class {
     class_type: &'static DTI,
     props: Vec<(String, Property)>,
+
+    // Unknown, kept so that files are written back the same way. These are
+    // the class info bits besides the object index, and the u64 after it.
+    class_info: u32,
+    unused: u64,
+    // The object it was read with, a database can have several for one type
+    object_idx: Option<u32>,
+}
+
+// Which object a class was read with isn't part of its contents
+impl PartialEq for Class {
+    fn eq(&self, other: &Self) -> bool {
+        self.class_type == other.class_type
+            && self.props == other.props
+            && self.class_info == other.class_info
+            && self.unused == other.unused
+    }
 }
 
 impl Class {
+    /// A class without any properties, to build files from scratch
+    pub fn new(class_type: &'static DTI) -> Self {
+        Self {
+            class_type,
+            props: vec![],
+            class_info: 0,
+            unused: 0,
+            object_idx: None,
+        }
+    }
+
     pub fn class_type(&self) -> &'static DTI {
         self.class_type
     }
@@ -108,95 +255,411 @@ impl Class {
         &self.props
     }
 
+    /// Properties are written in this order, and every class of the same
+    /// type needs the same ones
+    pub fn props_mut(&mut self) -> &mut Vec<(String, Property)> {
+        &mut self.props
+    }
+
     pub fn get_prop(&self, name: &str) -> Option<&Property> {
         self.props
             .iter()
             .find(|(prop_name, _)| prop_name == name)
             .map(|(_, prop)| prop)
     }
+
+    pub fn get_prop_mut(&mut self, name: &str) -> Option<&mut Property> {
+        self.props
+            .iter_mut()
+            .find(|(prop_name, _)| prop_name == name)
+            .map(|(_, prop)| prop)
+    }
+
+    // Whether both would have the same object in the database
+    fn same_layout(&self, other: &Class) -> bool {
+        self.class_type == other.class_type
+            && self.props.len() == other.props.len()
+            && self.props.iter().zip(&other.props).all(
+                |((name, prop), (other_name, other_prop))| {
+                    name == other_name && prop.bitfield == other_prop.bitfield
+                },
+            )
+    }
+
+    fn fits_object(&self, object: &ObjectInfo) -> bool {
+        self.class_type == object.dti
+            && self.props.len() == object.props.len()
+            && self
+                .props
+                .iter()
+                .zip(&object.props)
+                .all(|((name, prop), object_prop)| {
+                    *name == object_prop.name && prop.bitfield == object_prop.bitfield
+                })
+    }
+
+    /// This class and every class in its properties, depth first in the order
+    /// they're serialized
+    fn visit_classes<'a>(&'a self, f: &mut impl FnMut(&'a Class)) {
+        f(self);
+
+        for (_, prop) in &self.props {
+            for value in &prop.values {
+                if let PropertyValue::Class(Some(class)) = value {
+                    class.visit_classes(f);
+                }
+            }
+        }
+    }
+}
+
+fn read_f32s<const N: usize, R: Read>(reader: &mut R) -> anyhow::Result<[f32; N]> {
+    util::read_struct::<[f32; N], _>(reader)
+}
+
+fn read_vector3<R: Read>(reader: &mut R) -> anyhow::Result<PaddedVector3> {
+    read_f32s(reader)
+}
+
+// Size in bytes of the layouts read_value uses, for types that have a fixed
+// one
+fn fixed_value_size(prop_type: PropType) -> Option<u32> {
+    Some(match prop_type {
+        PropType::bool | PropType::u8 | PropType::s8 => 1,
+        PropType::u16 | PropType::s16 => 2,
+        PropType::u32
+        | PropType::s32
+        | PropType::f32
+        | PropType::color
+        | PropType::event32
+        | PropType::rangeu16 => 4,
+        PropType::u64
+        | PropType::s64
+        | PropType::f64
+        | PropType::point
+        | PropType::size
+        | PropType::vector2
+        | PropType::event64
+        | PropType::time
+        | PropType::float2
+        | PropType::easecurve
+        | PropType::range
+        | PropType::rangef
+        | PropType::pointf
+        | PropType::sizef => 8,
+        PropType::float3 => 12,
+        PropType::rect
+        | PropType::vector3
+        | PropType::vector4
+        | PropType::quaternion
+        | PropType::float4
+        | PropType::rectf
+        | PropType::Plane
+        | PropType::sphere => 16,
+        PropType::line
+        | PropType::linesegment
+        | PropType::ray
+        | PropType::aabb
+        | PropType::ellpsoid
+        | PropType::linesegment4
+        | PropType::aabb4 => 32,
+        // Guessed, see is_guessed_layout
+        PropType::cone => 32,
+        PropType::float3x3 => 36,
+        PropType::matrix33 | PropType::float4x3 | PropType::float3x4 | PropType::triangle => 48,
+        // Guessed, see is_guessed_layout
+        PropType::capsule | PropType::cylinder | PropType::torus => 48,
+        PropType::matrix44 | PropType::float4x4 | PropType::hermitecurve => 64,
+        PropType::obb => 80,
+        _ => return None,
+    })
+}
+
+// Layouts that were worked out from a few files rather than from the engine,
+// and might not match other games. Cone, torus, capsule and cylinder are the
+// least certain, their sizes are only what the known files happen to use.
+fn is_guessed_layout(prop_type: PropType) -> bool {
+    matches!(
+        prop_type,
+        PropType::color
+            | PropType::time
+            | PropType::easecurve
+            | PropType::hermitecurve
+            | PropType::Plane
+            | PropType::sphere
+            | PropType::capsule
+            | PropType::obb
+            | PropType::cylinder
+            | PropType::cone
+            | PropType::torus
+            | PropType::ellpsoid
+    )
+}
+
+fn read_raw_value<R: Read>(reader: &mut R, size: u32) -> anyhow::Result<PropertyValue> {
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data)?;
+
+    Ok(PropertyValue::Raw(data))
 }
 
-fn read_static_prop<R: Read + Seek>(
+fn read_value<R: Read + Seek>(
     reader: &mut R,
     prop: &PropertyInfo,
     objects: &[ObjectInfo],
-) -> anyhow::Result<Property> {
-    // array len?
-    let array_len = util::read_struct::<u32, _>(reader)?;
-    debug!("read_static_prop len: {}", array_len);
-
-    Ok(Property(
-        (0..array_len)
-            .map(|_idx| {
-                Ok(match prop.prop_type {
-                    PropType::class | PropType::classref => {
-                        PropertyValue::Class(read_class(reader, objects)?)
-                    }
-                    PropType::u16 => PropertyValue::U16(util::read_struct::<u16, _>(reader)?),
-                    PropType::vector3 => {
-                        let v = PropertyValue::Vector3(
-                            util::read_struct::<f32, _>(reader)?,
-                            util::read_struct::<f32, _>(reader)?,
-                            util::read_struct::<f32, _>(reader)?,
-                        );
-
-                        assert_eq!(0.0, util::read_struct::<f32, _>(reader)?); // padding
-
-                        v
-                    }
-                    PropType::bool => PropertyValue::Bool(util::read_struct::<u8, _>(reader)? != 0),
-                    PropType::u8 => PropertyValue::U8(util::read_struct::<u8, _>(reader)?),
-                    PropType::f32 => PropertyValue::F32(util::read_struct::<f32, _>(reader)?),
-                    PropType::s32 => PropertyValue::S32(util::read_struct::<i32, _>(reader)?),
-                    PropType::u32 => PropertyValue::U32(util::read_struct::<u32, _>(reader)?),
-                    PropType::s16 => PropertyValue::S16(util::read_struct::<i16, _>(reader)?),
-                    PropType::s8 => PropertyValue::S8(util::read_struct::<i8, _>(reader)?),
-                    PropType::string => {
-                        PropertyValue::String(util::read_null_terminated_string(reader, 0x200)?)
-                    }
-
-                    _ => todo!("handle prop type: {:?}", prop.prop_type),
-                })
-            })
-            .collect::<anyhow::Result<Vec<PropertyValue>>>()?,
-    ))
+) -> anyhow::Result<PropertyValue> {
+    // A guessed layout that doesn't match the property's size would throw off
+    // everything after it, so it's kept as is. For any other type, the file
+    // is broken.
+    if let Some(size) = fixed_value_size(prop.prop_type) {
+        if size != prop.prop_size {
+            if !is_guessed_layout(prop.prop_type) {
+                bail!(
+                    "{} is {:?} with size {}, expected {}",
+                    prop.name,
+                    prop.prop_type,
+                    prop.prop_size,
+                    size
+                );
+            }
+
+            warn!(
+                "{} is {:?} with size {}, expected {}, reading it as raw bytes",
+                prop.name, prop.prop_type, prop.prop_size, size
+            );
+
+            return read_raw_value(reader, prop.prop_size);
+        }
+    }
+
+    Ok(match prop.prop_type {
+        PropType::class | PropType::classref => PropertyValue::Class(read_class(reader, objects)?),
+        PropType::custom => {
+            let num_customs = util::read_struct::<u8, _>(reader)?;
+            let custom_params_values = (0..num_customs)
+                .map(|_| Ok(read_null_terminated_string(reader, 0x80)?))
+                .collect::<anyhow::Result<Vec<String>>>()?;
+
+            debug!("custom values {:?}", custom_params_values);
+
+            PropertyValue::Custom(custom_params_values)
+        }
+        PropType::bool => match util::read_struct::<u8, _>(reader)? {
+            0 => PropertyValue::Bool(false),
+            1 => PropertyValue::Bool(true),
+            value => PropertyValue::U8(value),
+        },
+        PropType::u8 => PropertyValue::U8(util::read_struct::<u8, _>(reader)?),
+        PropType::u16 => PropertyValue::U16(util::read_struct::<u16, _>(reader)?),
+        PropType::u32 => PropertyValue::U32(util::read_struct::<u32, _>(reader)?),
+        PropType::u64 => PropertyValue::U64(util::read_struct::<u64, _>(reader)?),
+        PropType::s8 => PropertyValue::S8(util::read_struct::<i8, _>(reader)?),
+        PropType::s16 => PropertyValue::S16(util::read_struct::<i16, _>(reader)?),
+        PropType::s32 => PropertyValue::S32(util::read_struct::<i32, _>(reader)?),
+        PropType::s64 => PropertyValue::S64(util::read_struct::<i64, _>(reader)?),
+        PropType::f32 => PropertyValue::F32(util::read_struct::<f32, _>(reader)?),
+        PropType::f64 => PropertyValue::F64(util::read_struct::<f64, _>(reader)?),
+        PropType::string | PropType::cstring => {
+            PropertyValue::String(util::read_null_terminated_string(reader, 0x200)?)
+        }
+        PropType::color => {
+            let [r, g, b, a] = util::read_struct::<[u8; 4], _>(reader)?;
+            PropertyValue::Color(r, g, b, a)
+        }
+        PropType::point => {
+            let [x, y] = util::read_struct::<[i32; 2], _>(reader)?;
+            PropertyValue::Point(x, y)
+        }
+        PropType::size => {
+            let [w, h] = util::read_struct::<[i32; 2], _>(reader)?;
+            PropertyValue::Size(w, h)
+        }
+        PropType::rect => {
+            let [l, t, r, b] = util::read_struct::<[i32; 4], _>(reader)?;
+            PropertyValue::Rect(l, t, r, b)
+        }
+        PropType::matrix44 => PropertyValue::Matrix44(util::read_struct(reader)?),
+        PropType::vector2 => {
+            let [x, y] = read_f32s(reader)?;
+            PropertyValue::Vector2(x, y)
+        }
+        PropType::vector3 => {
+            let [x, y, z, padding] = read_vector3(reader)?;
+            PropertyValue::Vector3(x, y, z, padding)
+        }
+        PropType::vector4 => {
+            let [x, y, z, w] = read_f32s(reader)?;
+            PropertyValue::Vector4(x, y, z, w)
+        }
+        PropType::quaternion => {
+            let [x, y, z, w] = read_f32s(reader)?;
+            PropertyValue::Quaternion(x, y, z, w)
+        }
+        PropType::matrix33 => PropertyValue::Matrix33([
+            read_vector3(reader)?,
+            read_vector3(reader)?,
+            read_vector3(reader)?,
+        ]),
+        PropType::event32 => PropertyValue::Event32(util::read_struct::<u32, _>(reader)?),
+        PropType::event64 => PropertyValue::Event64(util::read_struct::<u64, _>(reader)?),
+        PropType::time => PropertyValue::Time(util::read_struct::<u64, _>(reader)?),
+        PropType::float2 => {
+            let [x, y] = read_f32s(reader)?;
+            PropertyValue::Float2(x, y)
+        }
+        PropType::float3 => {
+            let [x, y, z] = read_f32s(reader)?;
+            PropertyValue::Float3(x, y, z)
+        }
+        PropType::float4 => {
+            let [x, y, z, w] = read_f32s(reader)?;
+            PropertyValue::Float4(x, y, z, w)
+        }
+        PropType::float3x3 => PropertyValue::Float3x3(util::read_struct(reader)?),
+        PropType::float4x3 => PropertyValue::Float4x3(util::read_struct(reader)?),
+        PropType::float3x4 => PropertyValue::Float3x4(util::read_struct(reader)?),
+        PropType::float4x4 => PropertyValue::Float4x4(util::read_struct(reader)?),
+        PropType::easecurve => {
+            let [p1, p2] = read_f32s(reader)?;
+            PropertyValue::EaseCurve(p1, p2)
+        }
+        PropType::hermitecurve => {
+            PropertyValue::HermiteCurve(read_f32s(reader)?, read_f32s(reader)?)
+        }
+        PropType::range => {
+            let [min, max] = util::read_struct::<[i32; 2], _>(reader)?;
+            PropertyValue::Range(min, max)
+        }
+        PropType::rangef => {
+            let [min, max] = read_f32s(reader)?;
+            PropertyValue::RangeF(min, max)
+        }
+        PropType::rangeu16 => {
+            let [min, max] = util::read_struct::<[u16; 2], _>(reader)?;
+            PropertyValue::RangeU16(min, max)
+        }
+        PropType::pointf => {
+            let [x, y] = read_f32s(reader)?;
+            PropertyValue::PointF(x, y)
+        }
+        PropType::sizef => {
+            let [w, h] = read_f32s(reader)?;
+            PropertyValue::SizeF(w, h)
+        }
+        PropType::rectf => {
+            let [l, t, r, b] = read_f32s(reader)?;
+            PropertyValue::RectF(l, t, r, b)
+        }
+        PropType::line => PropertyValue::Line(read_vector3(reader)?, read_vector3(reader)?),
+        PropType::linesegment => {
+            PropertyValue::LineSegment(read_vector3(reader)?, read_vector3(reader)?)
+        }
+        PropType::ray => PropertyValue::Ray(read_vector3(reader)?, read_vector3(reader)?),
+        PropType::Plane => {
+            let [x, y, z, dist] = read_f32s(reader)?;
+            PropertyValue::Plane([x, y, z], dist)
+        }
+        PropType::sphere => {
+            let [x, y, z, r] = read_f32s(reader)?;
+            PropertyValue::Sphere([x, y, z], r)
+        }
+        PropType::capsule => {
+            let p0 = read_vector3(reader)?;
+            let p1 = read_vector3(reader)?;
+            let [r, padding @ ..] = read_f32s::<4, _>(reader)?;
+            PropertyValue::Capsule(p0, p1, r, padding)
+        }
+        PropType::aabb => PropertyValue::Aabb(read_vector3(reader)?, read_vector3(reader)?),
+        PropType::obb => PropertyValue::Obb(util::read_struct(reader)?, read_vector3(reader)?),
+        PropType::cylinder => {
+            let p0 = read_vector3(reader)?;
+            let p1 = read_vector3(reader)?;
+            let [r, padding @ ..] = read_f32s::<4, _>(reader)?;
+            PropertyValue::Cylinder(p0, p1, r, padding)
+        }
+        PropType::triangle => PropertyValue::Triangle([
+            read_vector3(reader)?,
+            read_vector3(reader)?,
+            read_vector3(reader)?,
+        ]),
+        PropType::cone => {
+            let [x0, y0, z0, r0, x1, y1, z1, r1] = read_f32s(reader)?;
+            PropertyValue::Cone([x0, y0, z0], r0, [x1, y1, z1], r1)
+        }
+        PropType::torus => {
+            let pos = read_vector3(reader)?;
+            let axis = read_vector3(reader)?;
+            let [r, cr, padding @ ..] = read_f32s::<4, _>(reader)?;
+            PropertyValue::Torus(pos, axis, r, cr, padding)
+        }
+        PropType::ellpsoid => {
+            PropertyValue::Ellipsoid(read_vector3(reader)?, read_vector3(reader)?)
+        }
+        PropType::linesegment4 => {
+            PropertyValue::LineSegment4(read_f32s(reader)?, read_f32s(reader)?)
+        }
+        PropType::aabb4 => PropertyValue::Aabb4(read_f32s(reader)?, read_f32s(reader)?),
+
+        PropType::undefined
+        | PropType::property
+        | PropType::event
+        | PropType::group
+        | PropType::pagebegin
+        | PropType::pageend
+        | PropType::array
+        | PropType::propertylist
+        | PropType::groupend
+        | PropType::enumlist
+        | PropType::oscillator
+        | PropType::variable
+        | PropType::rect3d_xz
+        | PropType::rect3d
+        | PropType::rect3d_collision
+        | PropType::plane_xz
+        | PropType::ray_y
+        | PropType::type_end => read_raw_value(reader, prop.prop_size)?,
+    })
 }
 
-// TODO: can this be merged with read_static_prop?
-fn read_dynamic_prop<R: Read + Seek>(
+fn read_prop<R: Read + Seek>(
     reader: &mut R,
     prop: &PropertyInfo,
     objects: &[ObjectInfo],
 ) -> anyhow::Result<Property> {
     // array len?
     let array_len = util::read_struct::<u32, _>(reader)?;
-    debug!("read_dynamic_prop len: {}", array_len);
-
-    Ok(Property(
-        (0..array_len)
-            .map(|_idx| {
-                Ok(match prop.prop_type {
-                    PropType::custom => {
-                        let num_customs = util::read_struct::<u8, _>(reader)?;
-                        let custom_params_values = (0..num_customs)
-                            .map(|_| Ok(read_null_terminated_string(reader, 0x80)?))
-                            .collect::<anyhow::Result<Vec<String>>>()?;
-
-                        debug!("custom values {:?}", custom_params_values);
-
-                        PropertyValue::Custom(custom_params_values)
-                    }
-                    PropType::bool => PropertyValue::Bool(util::read_struct::<u8, _>(reader)? != 0),
-                    PropType::classref => PropertyValue::Class(read_class(reader, objects)?),
-                    PropType::s16 => PropertyValue::S16(util::read_struct::<i16, _>(reader)?),
-                    PropType::s32 => PropertyValue::S32(util::read_struct::<i32, _>(reader)?),
-                    PropType::u32 => PropertyValue::U32(util::read_struct::<u32, _>(reader)?),
-
-                    _ => todo!("handle prop type: {:?}", prop.prop_type),
-                })
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?,
-    ))
+    debug!("read_prop len: {}", array_len);
+
+    Ok(Property {
+        bitfield: prop.bitfield,
+        values: (0..array_len)
+            .map(|_idx| read_value(reader, prop, objects))
+            .collect::<anyhow::Result<Vec<PropertyValue>>>()?,
+    })
+}
+
+// Only warns, since files might be from a different version of the game than
+// the schema
+fn validate_props(dti: &DTI, props: &[PropertyInfo]) {
+    for prop in props {
+        let Some(schema_prop) = dti.prop(&prop.name) else {
+            warn!("{}::{} isn't in the DTI schema", dti.name(), prop.name);
+            continue;
+        };
+
+        if schema_prop.prop_type() != prop.prop_type || schema_prop.is_dynamic() != prop.is_dynamic
+        {
+            warn!(
+                "{}::{} is {:?} (dynamic {}), but the DTI schema has {:?} (dynamic {})",
+                dti.name(),
+                prop.name,
+                prop.prop_type,
+                prop.is_dynamic,
+                schema_prop.prop_type(),
+                schema_prop.is_dynamic()
+            );
+        }
+    }
 }
 
 fn read_class<R: Read + Seek>(
@@ -209,41 +672,38 @@ fn read_class<R: Read + Seek>(
 
     debug!("class_info: {:08x}", class_info);
 
-    if (class_info & 0xfffe) == 0xfffe {
+    if (class_info & NULL_CLASS_INFO) == NULL_CLASS_INFO {
         warn!("this returns null, is this the right behaviour");
         return Ok(None);
     }
 
-    let object_info = &objects[((class_info >> 1) & 0x7fff) as usize];
+    let object_idx = (class_info >> 1) & 0x7fff;
+    let object_info = objects
+        .get(object_idx as usize)
+        .ok_or_else(|| anyhow!("class uses object {}, which doesn't exist", object_idx))?;
     debug!("class object: {:08x?}", object_info);
 
-    let _unused_value = util::read_struct::<u64, _>(reader)?;
-    debug!("unused! : {:#?}", _unused_value); // What is this!
+    let unused_value = util::read_struct::<u64, _>(reader)?;
+    debug!("unused! : {:#?}", unused_value); // What is this!
 
     let props = object_info
         .props
         .iter()
-        .enumerate()
-        .map(|(_idx, prop)| {
+        .map(|prop| {
+            // Disabled properties are still in the file, and read like any
+            // other
             debug!(
-                "prop {} size {} type {:?} ({}) attr {} (dynamic {})",
+                "prop {} size {} type {:?} ({}) attr {} (dynamic {}, disabled {})",
                 prop.name,
                 prop.prop_size,
                 prop.prop_type,
                 prop.prop_raw_type,
                 prop.prop_attr,
-                prop.is_dynamic
+                prop.is_dynamic,
+                prop.is_disabled
             );
 
-            if prop.is_disabled {
-                todo!("disabled prop");
-            }
-
-            let value = if prop.is_dynamic {
-                read_dynamic_prop(reader, prop, objects)
-            } else {
-                read_static_prop(reader, prop, objects)
-            }?;
+            let value = read_prop(reader, prop, objects)?;
 
             debug!("prop {} value {:?}", prop.name, value);
 
@@ -254,110 +714,537 @@ fn read_class<R: Read + Seek>(
     Ok(Some(Class {
         class_type: object_info.dti,
         props,
+        class_info: class_info & !NULL_CLASS_INFO,
+        unused: unused_value,
+        object_idx: Some(object_idx),
     }))
 }
 
 pub fn deserialize<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Class> {
+    Ok(XfsFile::read(reader)?.root)
+}
+
+fn read_xfs<R: Read + Seek>(reader: &mut R) -> anyhow::Result<XfsFile> {
     let header: Header = util::read_struct(reader)?;
 
-    assert_eq!(header.magic.to_le_bytes(), "XFS\0".as_bytes());
-    assert_eq!((header.major_version as u16), 16);
+    if &header.magic.to_le_bytes() != XFS_MAGIC {
+        bail!("not an XFS file, magic is {:08x}", { header.magic });
+    }
+    if header.major_version != XFS_MAJOR_VERSION {
+        bail!("unsupported XFS version {}", { header.major_version });
+    }
+    if header.object_num == 0 {
+        bail!("XFS file has no objects");
+    }
 
     debug!("Header {:#?}", header);
 
     let mut database_bytes = vec![0u8; header.database_size as usize];
     reader.read_exact(&mut database_bytes)?;
 
-    let objects: Vec<_> = if header.object_num != 0 {
-        let object_ptrs = (0..header.object_num).map(|object_idx| {
-            let object_ptr_bytes =
-                &database_bytes[object_idx as usize * 8..(object_idx as usize + 1) * 8];
-            let object_ptr: u64 = u64::from_le_bytes(object_ptr_bytes.try_into().unwrap());
+    // Offsets in the database come from the file, so every access is checked
+    let database_slice = |offset: usize, size: usize| {
+        offset
+            .checked_add(size)
+            .and_then(|end| database_bytes.get(offset..end))
+            .ok_or_else(|| anyhow!("offset {:#x} is outside of the XFS database", offset))
+    };
+
+    let objects = (0..header.object_num as usize)
+        .map(|object_idx| {
+            let object_ptr_bytes = database_slice(object_idx * 8, size_of::<u64>())?;
+            let object_ptr = u64::from_le_bytes(object_ptr_bytes.try_into()?) as usize;
             debug!("object ptr {}: {:08x}", object_idx, object_ptr);
 
-            object_ptr as usize
-        });
-
-        object_ptrs
-            .map(|object_ptr| {
-                let object_bytes = &database_bytes[object_ptr..];
-                let object = RawObjectInfo::ref_from(&object_bytes[..size_of::<RawObjectInfo>()])
-                    .expect("couldn't read object");
-
-                let dti =
-                    DTI::from_hash(object.dti_hash.try_into().expect("DTI hash should be u32"))
-                        .ok_or_else(|| {
-                            anyhow::anyhow!("Couldn't get DTI for hash {:08x}", { object.dti_hash })
-                        })?;
-                let num_props = object.bitfield_0x8 & 0x7fff;
-                let is_init = (object.bitfield_0x8 & 0x8000) != 0;
-                assert!(!is_init, "TODO: handle this!");
-
-                debug!(
-                    "dti {:?} object {:?} propnum {}",
-                    dti.name(),
-                    object,
-                    num_props
+            let object =
+                RawObjectInfo::read_from(database_slice(object_ptr, size_of::<RawObjectInfo>())?)
+                    .ok_or_else(|| anyhow!("couldn't read object {}", object_idx))?;
+
+            let dti = DTI::from_hash(object.dti_hash)
+                .ok_or_else(|| anyhow!("Couldn't get DTI for hash {:08x}", { object.dti_hash }))?;
+            let num_props = object.bitfield_0x8 & 0x7fff;
+            let is_init = (object.bitfield_0x8 & 0x8000) != 0;
+            if is_init {
+                bail!(
+                    "object {} ({}) has the unsupported init bit",
+                    object_idx,
+                    dti.name()
                 );
+            }
 
-                let props = util::read_struct_array::<RawPropertyInfo>(
-                    &object_bytes[size_of::<RawObjectInfo>()..],
-                    num_props as usize,
-                )?
-                .enumerate()
-                .map(|(idx, prop)| {
-                    let prop = prop.expect("couldn't read prop");
-                    let prop_name_bytes = &database_bytes[prop.name as usize..];
-                    let prop_name_cstr = CStr::from_bytes_until_nul(prop_name_bytes)
-                        .expect("couldn't read prop name");
-
-                    // Property names are encoded as SHIFT-JIS
-                    let (prop_name, _encoding, _success) =
-                        encoding_rs::SHIFT_JIS.decode(prop_name_cstr.to_bytes());
-
-                    debug!("prop {} {}: {:x?}", idx, prop_name, prop);
-
-                    let prop_raw_type = prop.bitfield_0x8 & 0xff;
-                    let prop_attr = (prop.bitfield_0x8 >> 8) & 0xff;
-                    let prop_size = (prop.bitfield_0x8 >> 16) & 0x7fff;
-
-                    let is_dynamic = (prop_attr & dti::PROP_ATTR_DYNAMIC) != 0;
-                    let prop_type = PropType::from(prop_raw_type);
-                    // TODO: Is this correct?
-                    let is_disabled = (prop.bitfield_0x8 & !0x7fff_ffff) != 0;
-
-                    PropertyInfo {
-                        name: prop_name.to_string(),
-                        prop_raw_type,
-                        prop_attr,
-                        prop_size,
-                        is_dynamic,
-                        prop_type,
-                        is_disabled,
-                    }
-                })
-                .collect();
+            debug!(
+                "dti {:?} object {:?} propnum {}",
+                dti.name(),
+                object,
+                num_props
+            );
 
-                Ok(ObjectInfo { dti, props })
-            })
-            .collect::<anyhow::Result<Vec<ObjectInfo>>>()?
-    } else {
-        todo!("handle 0 object_num?");
-    };
+            let props_bytes = database_slice(
+                object_ptr + size_of::<RawObjectInfo>(),
+                num_props as usize * size_of::<RawPropertyInfo>(),
+            )?;
+            let props: Vec<_> =
+                util::read_struct_array::<RawPropertyInfo>(props_bytes, num_props as usize)?
+                    .enumerate()
+                    .map(|(idx, prop)| {
+                        let prop = prop.ok_or_else(|| anyhow!("couldn't read prop {}", idx))?;
+                        let prop_name_bytes = usize::try_from(prop.name)
+                            .ok()
+                            .and_then(|name_offset| database_bytes.get(name_offset..))
+                            .ok_or_else(|| {
+                                anyhow!("prop {} name is outside of the database", idx)
+                            })?;
+                        let prop_name_cstr = CStr::from_bytes_until_nul(prop_name_bytes)
+                            .map_err(|_| anyhow!("prop {} name isn't null terminated", idx))?;
+
+                        // Property names are encoded as SHIFT-JIS
+                        let (prop_name, _encoding, _success) =
+                            encoding_rs::SHIFT_JIS.decode(prop_name_cstr.to_bytes());
+
+                        debug!("prop {} {}: {:x?}", idx, prop_name, prop);
+
+                        let prop_raw_type = prop.bitfield_0x8 & 0xff;
+                        let prop_attr = (prop.bitfield_0x8 >> 8) & 0xff;
+                        let prop_size = (prop.bitfield_0x8 >> 16) & 0x7fff;
+
+                        let is_dynamic = (prop_attr & dti::PROP_ATTR_DYNAMIC) != 0;
+                        let prop_type = PropType::from(prop_raw_type);
+                        // TODO: Is this correct?
+                        let is_disabled = (prop.bitfield_0x8 & !0x7fff_ffff) != 0;
+
+                        Ok(PropertyInfo {
+                            name: prop_name.to_string(),
+                            bitfield: prop.bitfield_0x8,
+                            prop_raw_type,
+                            prop_attr,
+                            prop_size,
+                            is_dynamic,
+                            prop_type,
+                            is_disabled,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+
+            validate_props(dti, &props);
+
+            Ok(ObjectInfo { dti, props })
+        })
+        .collect::<anyhow::Result<Vec<ObjectInfo>>>()?;
 
     debug!("READING CLASSES");
     let class = read_class(reader, &objects)?;
 
-    Ok(class.expect("root class shouldn't be None"))
+    Ok(XfsFile {
+        root: class.ok_or_else(|| anyhow!("XFS root class is null"))?,
+        minor_version: header.minor_version,
+        max_object_id: Some(header.max_object_id),
+        reserved: header._reserved,
+        prp_header: None,
+        database: Some((database_bytes, objects)),
+    })
+}
+
+/// An XFS file, with what's needed to write it back out unchanged
+#[derive(Debug)]
+pub struct XfsFile {
+    root: Class,
+
+    minor_version: u16,
+    // None for new files, where it's counted
+    max_object_id: Option<u32>,
+    reserved: u32,
+    // What follows "PRPZ" for files with that header
+    prp_header: Option<[u8; PRP_HEADER_SIZE - 4]>,
+
+    // The database as read. It's written back as is as long as the classes
+    // still fit it, otherwise a new one is generated.
+    database: Option<(Vec<u8>, Vec<ObjectInfo>)>,
 }
 
+impl XfsFile {
+    /// A file that gets a newly generated database when saved
+    pub fn new(root: Class) -> Self {
+        Self {
+            root,
+            minor_version: 0,
+            max_object_id: None,
+            reserved: 0,
+            prp_header: None,
+            database: None,
+        }
+    }
+
+    /// Reads plain XFS files as well as ones with a PRPZ header
+    pub fn read<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Self> {
+        let mut magic_bytes = [0u8; 4];
+        reader.read_exact(&mut magic_bytes)?;
+
+        let prp_header = if &magic_bytes == PRP_MAGIC {
+            let mut prp_header = [0u8; PRP_HEADER_SIZE - 4];
+            reader.read_exact(&mut prp_header)?;
+
+            Some(prp_header)
+        } else {
+            reader.seek(SeekFrom::Current(-4))?;
+
+            None
+        };
+
+        Ok(Self {
+            prp_header,
+            ..read_xfs(reader)?
+        })
+    }
+
+    pub fn root(&self) -> &Class {
+        &self.root
+    }
+
+    pub fn root_mut(&mut self) -> &mut Class {
+        &mut self.root
+    }
+
+    /// The 8 bytes after "PRPZ", None if the file doesn't have that header
+    pub fn prp_header(&self) -> Option<&[u8; PRP_HEADER_SIZE - 4]> {
+        self.prp_header.as_ref()
+    }
+
+    pub fn set_prp_header(&mut self, prp_header: Option<[u8; PRP_HEADER_SIZE - 4]>) {
+        self.prp_header = prp_header;
+    }
+
+    pub fn save<W: Write>(&self, writer: &mut W) -> anyhow::Result<()> {
+        if let Some(prp_header) = &self.prp_header {
+            writer.write_all(PRP_MAGIC)?;
+            writer.write_all(prp_header)?;
+        }
+
+        write_xfs(
+            writer,
+            &self.root,
+            self.minor_version,
+            self.max_object_id
+                .unwrap_or_else(|| count_classes(&self.root)),
+            self.reserved,
+            self.database.as_ref(),
+        )
+    }
+}
+
+/// Write a class tree as an XFS file, with a newly generated database
+pub fn serialize<W: Write>(class: &Class, writer: &mut W) -> anyhow::Result<()> {
+    write_xfs(writer, class, 0, count_classes(class), 0, None)
+}
+
+fn write_xfs<W: Write>(
+    writer: &mut W,
+    root: &Class,
+    minor_version: u16,
+    max_object_id: u32,
+    reserved: u32,
+    original_database: Option<&(Vec<u8>, Vec<ObjectInfo>)>,
+) -> anyhow::Result<()> {
+    // Classes keep their own objects if the database is reused
+    let (database, object_num, object_indices) = match original_database {
+        Some((database, objects)) if match_objects(root, objects) => {
+            (database.clone(), objects.len(), None)
+        }
+        _ => {
+            let (database, object_num, object_indices) = build_database(root)?;
+            (database, object_num, Some(object_indices))
+        }
+    };
+
+    let header = Header {
+        magic: u32::from_le_bytes(*XFS_MAGIC),
+        major_version: XFS_MAJOR_VERSION,
+        minor_version,
+        max_object_id,
+        _reserved: reserved,
+        object_num: object_num.try_into()?,
+        database_size: database.len().try_into()?,
+    };
+
+    let mut data = vec![];
+    write_class(&mut data, Some(root), object_indices.as_ref())?;
+
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(&database)?;
+    writer.write_all(&data)?;
+
+    Ok(())
+}
+
+// Assumed to be what max_object_id counts for new files
+fn count_classes(root: &Class) -> u32 {
+    let mut num_classes = 0;
+    root.visit_classes(&mut |_| num_classes += 1);
+
+    num_classes
+}
+
+// Whether every class still fits the object it was read with
+fn match_objects(root: &Class, objects: &[ObjectInfo]) -> bool {
+    let mut matches = true;
+
+    root.visit_classes(&mut |class| {
+        let object = class
+            .object_idx
+            .and_then(|object_idx| objects.get(object_idx as usize));
+
+        if !object.is_some_and(|object| class.fits_object(object)) {
+            matches = false;
+        }
+    });
+
+    matches
+}
+
+// One object per class type, in the order they first show up. Returns the
+// database, the number of objects and DTI hash -> object index.
+fn build_database(root: &Class) -> anyhow::Result<(Vec<u8>, usize, HashMap<u32, u32>)> {
+    let mut objects: Vec<&Class> = vec![];
+    let mut mismatch = None;
+    root.visit_classes(&mut |class| {
+        let object = objects
+            .iter()
+            .find(|object| object.class_type == class.class_type);
+
+        match object {
+            Some(object) if !object.same_layout(class) => mismatch = Some(class.class_type),
+            Some(_) => {}
+            None => objects.push(class),
+        }
+    });
+
+    if let Some(dti) = mismatch {
+        bail!(
+            "classes of type {} don't all have the same properties",
+            dti.name()
+        );
+    }
+    if objects.len() >= (NULL_CLASS_INFO >> 1) as usize {
+        bail!("too many class types: {}", objects.len());
+    }
+
+    // Object pointers, then the objects, then the property names
+    let mut object_offsets = vec![];
+    let mut offset = objects.len() * size_of::<u64>();
+    for object in &objects {
+        object_offsets.push(offset);
+        offset += size_of::<RawObjectInfo>() + object.props.len() * size_of::<RawPropertyInfo>();
+    }
+    let strings_start = offset;
+
+    let mut database = vec![];
+    for object_offset in object_offsets {
+        database.extend_from_slice(&(object_offset as u64).to_le_bytes());
+    }
+
+    let mut strings = vec![];
+    let mut string_offsets: HashMap<&str, usize> = HashMap::new();
+    for object in &objects {
+        let raw_object = RawObjectInfo {
+            dti_hash: object.class_type.hash(),
+            padding_0x4: 0,
+            bitfield_0x8: object.props.len().try_into()?,
+            padding_0xc: 0,
+        };
+        if raw_object.bitfield_0x8 > 0x7fff {
+            bail!("{} has too many properties", object.class_type.name());
+        }
+        database.extend_from_slice(raw_object.as_bytes());
+
+        for (name, prop) in &object.props {
+            let name_offset = match string_offsets.get(name.as_str()) {
+                Some(name_offset) => *name_offset,
+                None => {
+                    let name_offset = strings_start + strings.len();
+                    write_string(&mut strings, name)?;
+                    string_offsets.insert(name, name_offset);
+
+                    name_offset
+                }
+            };
+
+            let raw_prop = RawPropertyInfo {
+                name: name_offset as u64,
+                bitfield_0x8: prop.bitfield,
+                pad: [0; 36],
+            };
+            database.extend_from_slice(raw_prop.as_bytes());
+        }
+    }
+    database.extend(strings);
+
+    let object_indices = objects
+        .iter()
+        .enumerate()
+        .map(|(idx, object)| (object.class_type.hash(), idx as u32))
+        .collect();
+
+    Ok((database, objects.len(), object_indices))
+}
+
+// Strings and property names are SHIFT-JIS
+fn write_string(data: &mut Vec<u8>, string: &str) -> anyhow::Result<()> {
+    let (encoded, _encoding, had_errors) = encoding_rs::SHIFT_JIS.encode(string);
+    if had_errors {
+        bail!("{:?} can't be encoded as SHIFT-JIS", string);
+    }
+
+    data.extend_from_slice(&encoded);
+    data.push(0);
+
+    Ok(())
+}
+
+// object_indices is DTI hash -> object index for a new database, otherwise
+// classes use the object they were read with
+fn write_class(
+    data: &mut Vec<u8>,
+    class: Option<&Class>,
+    object_indices: Option<&HashMap<u32, u32>>,
+) -> anyhow::Result<()> {
+    let Some(class) = class else {
+        data.extend_from_slice(&NULL_CLASS_INFO.to_le_bytes());
+        return Ok(());
+    };
+
+    let object_idx = match object_indices {
+        Some(object_indices) => object_indices.get(&class.class_type.hash()).copied(),
+        None => class.object_idx,
+    }
+    .ok_or_else(|| anyhow!("no object for {}", class.class_type.name()))?;
+    data.extend_from_slice(&(class.class_info | (object_idx << 1)).to_le_bytes());
+    data.extend_from_slice(&class.unused.to_le_bytes());
+
+    for (_, prop) in &class.props {
+        data.extend_from_slice(&(prop.values.len() as u32).to_le_bytes());
+
+        for value in &prop.values {
+            write_value(data, value, object_indices)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_f32s(data: &mut Vec<u8>, values: &[f32]) {
+    data.extend_from_slice(values.as_bytes());
+}
+
+fn write_vector3(data: &mut Vec<u8>, vector: &PaddedVector3) {
+    write_f32s(data, vector);
+}
+
+fn write_value(
+    data: &mut Vec<u8>,
+    value: &PropertyValue,
+    object_indices: Option<&HashMap<u32, u32>>,
+) -> anyhow::Result<()> {
+    match value {
+        PropertyValue::Class(class) => write_class(data, class.as_ref(), object_indices)?,
+        PropertyValue::Custom(values) => {
+            data.push(values.len().try_into()?);
+            for value in values {
+                write_string(data, value)?;
+            }
+        }
+        PropertyValue::Bool(value) => data.push(*value as u8),
+        PropertyValue::U8(value) => data.push(*value),
+        PropertyValue::U16(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::U32(value) | PropertyValue::Event32(value) => {
+            data.extend_from_slice(&value.to_le_bytes())
+        }
+        PropertyValue::U64(value) | PropertyValue::Event64(value) | PropertyValue::Time(value) => {
+            data.extend_from_slice(&value.to_le_bytes())
+        }
+        PropertyValue::S8(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::S16(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::S32(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::S64(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::F32(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::F64(value) => data.extend_from_slice(&value.to_le_bytes()),
+        PropertyValue::String(value) => write_string(data, value)?,
+        PropertyValue::Color(r, g, b, a) => data.extend_from_slice(&[*r, *g, *b, *a]),
+        PropertyValue::Point(x, y) | PropertyValue::Size(x, y) | PropertyValue::Range(x, y) => {
+            data.extend_from_slice([*x, *y].as_bytes())
+        }
+        PropertyValue::Rect(l, t, r, b) => data.extend_from_slice([*l, *t, *r, *b].as_bytes()),
+        PropertyValue::RangeU16(min, max) => data.extend_from_slice([*min, *max].as_bytes()),
+        PropertyValue::Vector2(x, y)
+        | PropertyValue::Float2(x, y)
+        | PropertyValue::EaseCurve(x, y)
+        | PropertyValue::RangeF(x, y)
+        | PropertyValue::PointF(x, y)
+        | PropertyValue::SizeF(x, y) => write_f32s(data, &[*x, *y]),
+        PropertyValue::Vector3(x, y, z, padding) => write_vector3(data, &[*x, *y, *z, *padding]),
+        PropertyValue::Float3(x, y, z) => write_f32s(data, &[*x, *y, *z]),
+        PropertyValue::Vector4(x, y, z, w)
+        | PropertyValue::Quaternion(x, y, z, w)
+        | PropertyValue::Float4(x, y, z, w)
+        | PropertyValue::RectF(x, y, z, w) => write_f32s(data, &[*x, *y, *z, *w]),
+        PropertyValue::Matrix44(matrix) | PropertyValue::Float4x4(matrix) => {
+            write_f32s(data, matrix.as_flattened())
+        }
+        PropertyValue::Float3x3(matrix) => write_f32s(data, matrix.as_flattened()),
+        PropertyValue::Float4x3(matrix) => write_f32s(data, matrix.as_flattened()),
+        PropertyValue::Float3x4(matrix) => write_f32s(data, matrix.as_flattened()),
+        PropertyValue::Matrix33(rows) | PropertyValue::Triangle(rows) => {
+            for row in rows {
+                write_vector3(data, row);
+            }
+        }
+        PropertyValue::HermiteCurve(x, y) => {
+            write_f32s(data, x);
+            write_f32s(data, y);
+        }
+        PropertyValue::Line(a, b)
+        | PropertyValue::LineSegment(a, b)
+        | PropertyValue::Ray(a, b)
+        | PropertyValue::Aabb(a, b)
+        | PropertyValue::Ellipsoid(a, b) => {
+            write_vector3(data, a);
+            write_vector3(data, b);
+        }
+        PropertyValue::Plane([x, y, z], w) | PropertyValue::Sphere([x, y, z], w) => {
+            write_f32s(data, &[*x, *y, *z, *w])
+        }
+        PropertyValue::Capsule(p0, p1, r, [pad0, pad1, pad2])
+        | PropertyValue::Cylinder(p0, p1, r, [pad0, pad1, pad2]) => {
+            write_vector3(data, p0);
+            write_vector3(data, p1);
+            write_f32s(data, &[*r, *pad0, *pad1, *pad2]);
+        }
+        PropertyValue::Obb(transform, extent) => {
+            write_f32s(data, transform.as_flattened());
+            write_vector3(data, extent);
+        }
+        PropertyValue::Cone([x0, y0, z0], r0, [x1, y1, z1], r1) => {
+            write_f32s(data, &[*x0, *y0, *z0, *r0, *x1, *y1, *z1, *r1])
+        }
+        PropertyValue::Torus(pos, axis, r, cr, [pad0, pad1]) => {
+            write_vector3(data, pos);
+            write_vector3(data, axis);
+            write_f32s(data, &[*r, *cr, *pad0, *pad1]);
+        }
+        PropertyValue::LineSegment4(a, b) | PropertyValue::Aabb4(a, b) => {
+            write_f32s(data, a);
+            write_f32s(data, b);
+        }
+        PropertyValue::Raw(value) => data.extend_from_slice(value),
+    }
+
+    Ok(())
+}
 
 pub fn prp_file_to_mtserializer<'a, R: Read + Seek>(
     file: &'a mut R,
 ) -> anyhow::Result<std::io::Cursor<Vec<u8>>> {
     let mut magic_bytes = [0u8; 4];
     file.read_exact(&mut magic_bytes)?;
-    let is_propparam = magic_bytes == "PRPZ".as_bytes();
+    let is_propparam = &magic_bytes == PRP_MAGIC;
 
     file.seek(std::io::SeekFrom::Start(0))?;
 
@@ -365,7 +1252,7 @@ pub fn prp_file_to_mtserializer<'a, R: Read + Seek>(
     file.read_to_end(&mut file_data)?;
 
     if is_propparam {
-        file_data.drain(..12); // past header
+        file_data.drain(..PRP_HEADER_SIZE); // past header
     };
 
     Ok(std::io::Cursor::new(file_data))
@@ -378,3 +1265,433 @@ fn test_struct_sizes() {
     assert_eq!(0x18, size_of::<Header>());
     assert_eq!(0x30, size_of::<RawPropertyInfo>());
 }
+
+#[test]
+fn test_serialize() {
+    use std::io::Cursor;
+
+    use crate::DTIs;
+
+    let mut resource_info = Class::new(&DTIs::sAppResource__cResourceInfo);
+    resource_info.props_mut().extend([
+        (
+            "mPath".to_string(),
+            Property::new(
+                PropType::string,
+                0,
+                8,
+                vec![PropertyValue::String("motion\\pl00".to_string())],
+            ),
+        ),
+        (
+            "mPrio".to_string(),
+            Property::new(PropType::u32, 0, 4, vec![PropertyValue::U32(3)]),
+        ),
+    ]);
+
+    let mut character = Class::new(&DTIs::nGO__rCharacter);
+    character.props_mut().extend([
+        (
+            "mpModel".to_string(),
+            Property::new(
+                PropType::custom,
+                dti::PROP_ATTR_DYNAMIC,
+                8,
+                vec![PropertyValue::Custom(vec![
+                    "rModel".to_string(),
+                    "model\\pl00".to_string(),
+                ])],
+            ),
+        ),
+        (
+            "mBoxOffset".to_string(),
+            Property::new(
+                PropType::vector3,
+                0,
+                16,
+                vec![PropertyValue::Vector3(1.0, -2.5, 3.0, 0.0)],
+            ),
+        ),
+        (
+            "PartsDisp".to_string(),
+            Property::new(
+                PropType::bool,
+                dti::PROP_ATTR_ARRAY | dti::PROP_ATTR_DYNAMIC,
+                1,
+                vec![PropertyValue::Bool(true), PropertyValue::Bool(false)],
+            ),
+        ),
+        (
+            "mMotion".to_string(),
+            Property::new(
+                PropType::class,
+                0,
+                8,
+                vec![PropertyValue::Class(Some(resource_info))],
+            ),
+        ),
+        (
+            "mScriptPartsDisp".to_string(),
+            Property::new(PropType::class, 0, 8, vec![PropertyValue::Class(None)]),
+        ),
+        (
+            "首関節番号".to_string(),
+            Property::new(PropType::s32, 0, 4, vec![PropertyValue::S32(-1)]),
+        ),
+    ]);
+
+    let mut data = vec![];
+    serialize(&character, &mut data).unwrap();
+    assert_eq!(character, deserialize(&mut Cursor::new(&data)).unwrap());
+
+    // Unchanged files are written back as they were
+    let mut file = XfsFile::read(&mut Cursor::new(&data)).unwrap();
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(data, saved);
+
+    // Changing values keeps the database
+    *file
+        .root_mut()
+        .get_prop_mut("首関節番号")
+        .unwrap()
+        .values_mut() = vec![PropertyValue::S32(5)];
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(data.len(), saved.len());
+    let database_end = size_of::<Header>() + file.database.as_ref().unwrap().0.len();
+    assert_eq!(data[..database_end], saved[..database_end]);
+    assert_eq!(
+        [PropertyValue::S32(5)],
+        deserialize(&mut Cursor::new(&saved))
+            .unwrap()
+            .get_prop("首関節番号")
+            .unwrap()
+            .values()
+    );
+
+    // New properties get a new database
+    file.root_mut().props_mut().push((
+        "補間方法".to_string(),
+        Property::new(PropType::u32, 0, 4, vec![PropertyValue::U32(2)]),
+    ));
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(*file.root(), deserialize(&mut Cursor::new(&saved)).unwrap());
+
+    // PRPZ headers are kept
+    let mut prp_data = b"PRPZ\x01\x00\x00\x00\x02\x00\x00\x00".to_vec();
+    prp_data.extend(&data);
+    let file = XfsFile::read(&mut Cursor::new(&prp_data)).unwrap();
+    assert_eq!(Some(&[1, 0, 0, 0, 2, 0, 0, 0]), file.prp_header());
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(prp_data, saved);
+    assert_eq!(
+        character,
+        deserialize(&mut prp_file_to_mtserializer(&mut Cursor::new(&prp_data)).unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn test_prop_types() {
+    use std::io::Cursor;
+
+    let v3 = [1.0, 2.0, 3.0];
+    let v4 = [1.0, 2.0, 3.0, 4.0];
+    // Padding isn't always 0
+    let padded = [1.0, 2.0, 3.0, -1.0];
+    let m44 = [v4, v4, v4, v4];
+
+    // Every type with a fixed size, along with it
+    let values = [
+        (PropType::bool, 1, PropertyValue::Bool(true)),
+        (PropType::bool, 1, PropertyValue::U8(2)),
+        (PropType::u8, 1, PropertyValue::U8(1)),
+        (PropType::u16, 2, PropertyValue::U16(2)),
+        (PropType::u32, 4, PropertyValue::U32(3)),
+        (PropType::u64, 8, PropertyValue::U64(4)),
+        (PropType::s8, 1, PropertyValue::S8(-1)),
+        (PropType::s16, 2, PropertyValue::S16(-2)),
+        (PropType::s32, 4, PropertyValue::S32(-3)),
+        (PropType::s64, 8, PropertyValue::S64(-4)),
+        (PropType::f32, 4, PropertyValue::F32(0.5)),
+        (PropType::f64, 8, PropertyValue::F64(0.25)),
+        (PropType::color, 4, PropertyValue::Color(1, 2, 3, 4)),
+        (PropType::point, 8, PropertyValue::Point(1, -1)),
+        (PropType::size, 8, PropertyValue::Size(640, 480)),
+        (PropType::rect, 16, PropertyValue::Rect(0, 0, 640, 480)),
+        (PropType::matrix44, 64, PropertyValue::Matrix44(m44)),
+        (PropType::vector2, 8, PropertyValue::Vector2(1.0, 2.0)),
+        (
+            PropType::vector3,
+            16,
+            PropertyValue::Vector3(1.0, 2.0, 3.0, -1.0),
+        ),
+        (
+            PropType::vector4,
+            16,
+            PropertyValue::Vector4(1.0, 2.0, 3.0, 4.0),
+        ),
+        (
+            PropType::quaternion,
+            16,
+            PropertyValue::Quaternion(0.0, 0.0, 0.0, 1.0),
+        ),
+        (
+            PropType::matrix33,
+            48,
+            PropertyValue::Matrix33([padded, padded, padded]),
+        ),
+        (PropType::event32, 4, PropertyValue::Event32(5)),
+        (PropType::event64, 8, PropertyValue::Event64(6)),
+        (PropType::time, 8, PropertyValue::Time(7)),
+        (PropType::float2, 8, PropertyValue::Float2(1.0, 2.0)),
+        (PropType::float3, 12, PropertyValue::Float3(1.0, 2.0, 3.0)),
+        (
+            PropType::float4,
+            16,
+            PropertyValue::Float4(1.0, 2.0, 3.0, 4.0),
+        ),
+        (
+            PropType::float3x3,
+            36,
+            PropertyValue::Float3x3([v3, v3, v3]),
+        ),
+        (
+            PropType::float4x3,
+            48,
+            PropertyValue::Float4x3([v3, v3, v3, v3]),
+        ),
+        (
+            PropType::float3x4,
+            48,
+            PropertyValue::Float3x4([v4, v4, v4]),
+        ),
+        (PropType::float4x4, 64, PropertyValue::Float4x4(m44)),
+        (PropType::easecurve, 8, PropertyValue::EaseCurve(0.25, 0.75)),
+        (
+            PropType::hermitecurve,
+            64,
+            PropertyValue::HermiteCurve([0.5; 8], [1.5; 8]),
+        ),
+        (PropType::range, 8, PropertyValue::Range(-1, 1)),
+        (PropType::rangef, 8, PropertyValue::RangeF(-1.0, 1.0)),
+        (PropType::rangeu16, 4, PropertyValue::RangeU16(1, 2)),
+        (PropType::pointf, 8, PropertyValue::PointF(1.0, 2.0)),
+        (PropType::sizef, 8, PropertyValue::SizeF(1.0, 2.0)),
+        (
+            PropType::rectf,
+            16,
+            PropertyValue::RectF(1.0, 2.0, 3.0, 4.0),
+        ),
+        (PropType::line, 32, PropertyValue::Line(padded, padded)),
+        (
+            PropType::linesegment,
+            32,
+            PropertyValue::LineSegment(padded, padded),
+        ),
+        (PropType::ray, 32, PropertyValue::Ray(padded, padded)),
+        (PropType::Plane, 16, PropertyValue::Plane(v3, 4.0)),
+        (PropType::sphere, 16, PropertyValue::Sphere(v3, 4.0)),
+        (
+            PropType::capsule,
+            48,
+            PropertyValue::Capsule(padded, padded, 4.0, v3),
+        ),
+        (PropType::aabb, 32, PropertyValue::Aabb(padded, padded)),
+        (PropType::obb, 80, PropertyValue::Obb(m44, padded)),
+        (
+            PropType::cylinder,
+            48,
+            PropertyValue::Cylinder(padded, padded, 4.0, v3),
+        ),
+        (
+            PropType::triangle,
+            48,
+            PropertyValue::Triangle([padded, padded, padded]),
+        ),
+        (PropType::cone, 32, PropertyValue::Cone(v3, 4.0, v3, 5.0)),
+        (
+            PropType::torus,
+            48,
+            PropertyValue::Torus(padded, padded, 4.0, 5.0, [6.0, 7.0]),
+        ),
+        (
+            PropType::ellpsoid,
+            32,
+            PropertyValue::Ellipsoid(padded, padded),
+        ),
+        (
+            PropType::linesegment4,
+            32,
+            PropertyValue::LineSegment4(v4, v4),
+        ),
+        (PropType::aabb4, 32, PropertyValue::Aabb4(v4, v4)),
+        (PropType::oscillator, 12, PropertyValue::Raw(vec![7; 12])),
+        (PropType::rect3d, 0, PropertyValue::Raw(vec![])),
+    ];
+
+    let mut class = Class::new(&crate::DTIs::MtObject);
+    for (prop_type, size, value) in values {
+        let mut data = vec![];
+        write_value(&mut data, &value, None).unwrap();
+        assert_eq!(size as usize, data.len(), "{:?}", prop_type);
+        if !matches!(value, PropertyValue::Raw(_)) {
+            assert_eq!(Some(size), fixed_value_size(prop_type), "{:?}", prop_type);
+        }
+
+        class.props_mut().push((
+            format!("m{:?}", prop_type),
+            Property::new(prop_type, 0, size, vec![value]),
+        ));
+    }
+
+    let mut data = vec![];
+    serialize(&class, &mut data).unwrap();
+    assert_eq!(class, deserialize(&mut Cursor::new(&data)).unwrap());
+
+    // Including padding and bools that aren't 0 or 1
+    let file = XfsFile::read(&mut Cursor::new(&data)).unwrap();
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(data, saved);
+
+    // A size that doesn't match a guessed layout is read as is, without
+    // throwing off the properties after it
+    let mut class = Class::new(&crate::DTIs::MtObject);
+    class.props_mut().extend([
+        (
+            "mTime".to_string(),
+            Property::new(PropType::time, 0, 12, vec![PropertyValue::Raw(vec![1; 12])]),
+        ),
+        (
+            "mNext".to_string(),
+            Property::new(PropType::u32, 0, 4, vec![PropertyValue::U32(5)]),
+        ),
+    ]);
+
+    let mut data = vec![];
+    serialize(&class, &mut data).unwrap();
+    assert_eq!(class, deserialize(&mut Cursor::new(&data)).unwrap());
+
+    // Any other layout is known, so a different size is an error
+    let mut class = Class::new(&crate::DTIs::MtObject);
+    class.props_mut().push((
+        "mValue".to_string(),
+        Property::new(PropType::u32, 0, 8, vec![PropertyValue::Raw(vec![1; 8])]),
+    ));
+
+    let mut data = vec![];
+    serialize(&class, &mut data).unwrap();
+    assert!(deserialize(&mut Cursor::new(&data)).is_err());
+}
+
+#[test]
+fn test_malformed_xfs() {
+    use std::io::Cursor;
+
+    let mut class = Class::new(&crate::DTIs::MtObject);
+    class.props_mut().push((
+        "mValue".to_string(),
+        Property::new(PropType::u32, 0, 4, vec![PropertyValue::U32(1)]),
+    ));
+    let mut data = vec![];
+    serialize(&class, &mut data).unwrap();
+
+    let database_start = size_of::<Header>();
+    let data_start = data.len() - 20;
+
+    let corrupt = |offset: usize, bytes: &[u8]| {
+        let mut data = data.clone();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        deserialize(&mut Cursor::new(data))
+    };
+
+    // Errors instead of panics
+    assert!(corrupt(0, b"XFZ\0").is_err());
+    assert!(corrupt(4, &1u16.to_le_bytes()).is_err());
+    // No objects
+    assert!(corrupt(0x10, &0u32.to_le_bytes()).is_err());
+    // Object pointer, then the property name pointer
+    assert!(corrupt(database_start, &u64::MAX.to_le_bytes()).is_err());
+    assert!(corrupt(database_start + 0x18, &u64::MAX.to_le_bytes()).is_err());
+    // Init bit
+    assert!(corrupt(database_start + 0x10, &0x8001u32.to_le_bytes()).is_err());
+    // Root class with a missing object, and a null root
+    assert!(corrupt(data_start, &(5u32 << 1).to_le_bytes()).is_err());
+    assert!(corrupt(data_start, &NULL_CLASS_INFO.to_le_bytes()).is_err());
+    // Truncated
+    assert!(deserialize(&mut Cursor::new(&data[..data.len() - 1])).is_err());
+
+    // Disabled properties are read like any other
+    let disabled = corrupt(database_start + 0x23, &[0x80]).unwrap();
+    assert_eq!(
+        [PropertyValue::U32(1)],
+        disabled.get_prop("mValue").unwrap().values()
+    );
+}
+
+#[test]
+fn test_duplicate_objects() {
+    use std::io::Cursor;
+
+    // Two objects for MtObject with different properties, the root uses the
+    // second one and its child the first
+    let prop = |name_offset: u64, prop_type: PropType, size: u32| RawPropertyInfo {
+        name: name_offset,
+        bitfield_0x8: prop_type as u32 | (size << 16),
+        pad: [0; 36],
+    };
+    let object = RawObjectInfo {
+        dti_hash: crate::DTIs::MtObject.hash(),
+        padding_0x4: 0,
+        bitfield_0x8: 1,
+        padding_0xc: 0,
+    };
+
+    let mut database = vec![];
+    database.extend_from_slice(&16u64.to_le_bytes());
+    database.extend_from_slice(&80u64.to_le_bytes());
+    database.extend_from_slice(object.as_bytes());
+    database.extend_from_slice(prop(144, PropType::u32, 4).as_bytes());
+    database.extend_from_slice(object.as_bytes());
+    database.extend_from_slice(prop(146, PropType::class, 8).as_bytes());
+    database.extend_from_slice(b"a\0child\0");
+
+    let header = Header {
+        magic: u32::from_le_bytes(*XFS_MAGIC),
+        major_version: XFS_MAJOR_VERSION,
+        minor_version: 0,
+        max_object_id: 2,
+        _reserved: 0,
+        object_num: 2,
+        database_size: database.len() as u32,
+    };
+
+    let mut data = header.as_bytes().to_vec();
+    data.extend(database);
+    // Root: object 1, one child
+    data.extend_from_slice(&(1u32 << 1).to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&1u32.to_le_bytes());
+    // Child: object 0, a = 7
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&7u32.to_le_bytes());
+
+    let file = XfsFile::read(&mut Cursor::new(&data)).unwrap();
+    let PropertyValue::Class(Some(child)) = &file.root().get_prop("child").unwrap().values()[0]
+    else {
+        panic!("expected child class");
+    };
+    assert_eq!(
+        [PropertyValue::U32(7)],
+        child.get_prop("a").unwrap().values()
+    );
+
+    let mut saved = vec![];
+    file.save(&mut saved).unwrap();
+    assert_eq!(data, saved);
+}
@@ -1,25 +1,158 @@
 use std::{
-    ffi::CStr,
-    io::{Cursor, Read, Seek, Write},
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+    fmt::Display,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
     mem::size_of,
     path::Path,
     sync::Mutex,
 };
 
-use flate2::{read::ZlibDecoder, write::ZlibEncoder};
-use log::{debug, trace};
+use anyhow::anyhow;
+use blowfish::{
+    cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+    BlowfishLE,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use log::{debug, trace, warn};
+use memmap2::Mmap;
 use rayon::prelude::*;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
-use crate::{util, DTI};
+use crate::{resource_path::ResourcePath, util, DTI};
 
 const ARCHIVE_MAGIC: u32 = u32::from_be(0x41524300); // "ARC\0"
-const ARCHIVE_VERSION: u16 = 7;
+const ARCHIVE_MAGIC_ENCRYPTED: u32 = u32::from_be(0x41524343); // "ARCC"
 
 const ORGSIZE_MASK: u32 = 2_u32.pow(29) - 1;
 const QUALITY_MASK: u32 = 2_u32.pow(3) - 1;
 
-const PATH_MAXLEN: usize = 127;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Endianness {
+    Little,
+    // Console builds, the magic reads as "\0CRA"
+    Big,
+}
+
+impl Endianness {
+    fn read_u32(self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        match self {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+/// Layout of the header and table of contents, which differs between MT
+/// Framework builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveFormat {
+    pub version: u16,
+    pub endianness: Endianness,
+    /// Size of the path field in each resource info, including the null byte
+    pub path_size: usize,
+}
+
+impl ArchiveFormat {
+    pub const fn new(version: u16, endianness: Endianness, path_size: usize) -> Self {
+        Self {
+            version,
+            endianness,
+            path_size,
+        }
+    }
+
+    fn resource_info_size(&self) -> usize {
+        // path + dti_type + size_compressed + bitfield_orgsize_quality + offset
+        self.path_size + (4 * size_of::<u32>())
+    }
+
+    fn data_start(&self, num_resources: usize) -> usize {
+        size_of::<ArchiveHeader>() + (num_resources * self.resource_info_size())
+    }
+}
+
+/// Parsed from "<version>:<le|be>:<path_size>", e.g. "7:le:128"
+impl std::str::FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow!(
+                "invalid archive format {:?}, expected <version>:<le|be>:<path_size>",
+                s
+            )
+        };
+
+        let [version, endianness, path_size] = s.split(':').collect::<Vec<_>>()[..] else {
+            return Err(invalid());
+        };
+
+        let endianness = match endianness.to_ascii_lowercase().as_str() {
+            "le" => Endianness::Little,
+            "be" => Endianness::Big,
+            _ => return Err(invalid()),
+        };
+        let path_size: usize = path_size.parse().map_err(|_| invalid())?;
+        if path_size == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self::new(
+            version.parse().map_err(|_| invalid())?,
+            endianness,
+            path_size,
+        ))
+    }
+}
+
+impl Default for ArchiveFormat {
+    fn default() -> Self {
+        KNOWN_FORMATS[0]
+    }
+}
+
+/// Formats that ArchiveFile::new will detect, one per version and endianness.
+/// Only layouts that have been checked against real archives are listed,
+/// anything else has to be opened with ArchiveFile::with_format. The size and
+/// quality bitfield is assumed to be split the same way for every format.
+pub const KNOWN_FORMATS: &[ArchiveFormat] = &[ArchiveFormat::new(7, Endianness::Little, 128)];
+
+/// Blowfish key for encrypted ("ARCC") archives, each game has its own. The
+/// table of contents and the data for each resource are encrypted separately,
+/// with the cipher working on little endian words.
+#[derive(Clone)]
+pub struct ArchiveKey(BlowfishLE);
+
+impl ArchiveKey {
+    pub fn new(key: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self(BlowfishLE::new_from_slice(key).map_err(|_| {
+            anyhow!("invalid blowfish key length: {}", key.len())
+        })?))
+    }
+
+    // Only whole blocks are encrypted, trailing bytes are left as they are
+    fn decrypt(&self, data: &mut [u8]) {
+        for block in data.chunks_exact_mut(8) {
+            self.0.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+    }
+
+    fn encrypt(&self, data: &mut [u8]) {
+        for block in data.chunks_exact_mut(8) {
+            self.0.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+    }
+}
 
 #[repr(C, packed)]
 #[derive(Debug, FromBytes, FromZeroes, AsBytes)]
@@ -29,10 +162,21 @@ struct ArchiveHeader {
     num_resources: u16,
 }
 
-#[repr(C, packed)]
-#[derive(Debug, FromBytes, FromZeroes, AsBytes)]
+impl ArchiveHeader {
+    // Fields are stored in native (little) endian order, convert to or from
+    // big endian
+    fn swap_bytes(self) -> Self {
+        Self {
+            magic: self.magic.swap_bytes(),
+            version: self.version.swap_bytes(),
+            num_resources: self.num_resources.swap_bytes(),
+        }
+    }
+}
+
+#[derive(Debug)]
 struct RawResourceInfo {
-    path: [u8; PATH_MAXLEN + 1], // + null byte
+    path: Vec<u8>, // + null byte
     dti_type: u32,
     size_compressed: u32,
     // orgsize: 29, quality: 3
@@ -40,10 +184,76 @@ struct RawResourceInfo {
     offset: u32,
 }
 
+impl RawResourceInfo {
+    fn read<R: Read>(
+        reader: &mut R,
+        format: &ArchiveFormat,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<Self> {
+        let mut bytes = vec![0u8; format.resource_info_size()];
+        reader.read_exact(&mut bytes)?;
+
+        if let Some(key) = key {
+            key.decrypt(&mut bytes);
+        }
+
+        let (path, fields) = bytes.split_at(format.path_size);
+        let field = |idx: usize| format.endianness.read_u32(&fields[idx * 4..(idx + 1) * 4]);
+
+        Ok(Self {
+            path: path.to_vec(),
+            dti_type: field(0),
+            size_compressed: field(1),
+            bitfield_orgsize_quality: field(2),
+            offset: field(3),
+        })
+    }
+
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        format: &ArchiveFormat,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        assert_eq!(self.path.len(), format.path_size);
+
+        let mut bytes = self.path.clone();
+        for field in [
+            self.dti_type,
+            self.size_compressed,
+            self.bitfield_orgsize_quality,
+            self.offset,
+        ] {
+            bytes.extend_from_slice(&format.endianness.u32_bytes(field));
+        }
+
+        if let Some(key) = key {
+            key.encrypt(&mut bytes);
+        }
+
+        writer.write_all(&bytes)?;
+
+        Ok(())
+    }
+}
+
+/// Extension used for resources that don't have one in the DTI table, derived
+/// from the type hash so that they can still be written to disk and packed
+/// back into an archive.
+pub fn file_ext_for_dti_hash(dti_hash: u32) -> String {
+    match DTI::from_hash(dti_hash).and_then(|dti| dti.file_ext()) {
+        Some(file_ext) => file_ext.to_string(),
+        None => format!("{:08x}", dti_hash),
+    }
+}
+
 #[derive(Debug)]
 pub struct ResourceInfo {
     path: String,
-    dti: &'static DTI,
+    path_terminated: bool,
+    dti_hash: u32,
+    // None if the type isn't in the DTI table, eg. from another game
+    dti: Option<&'static DTI>,
     size_compressed: u32,
     size_uncompressed: u32,
 
@@ -55,39 +265,372 @@ impl ResourceInfo {
         &self.path
     }
 
-    pub fn dti(&self) -> &'static DTI {
+    pub fn dti(&self) -> Option<&'static DTI> {
         self.dti
     }
 
+    pub fn dti_hash(&self) -> u32 {
+        self.dti_hash
+    }
+
+    /// DTI name, or the hex hash for unknown types
+    pub fn dti_name(&self) -> String {
+        match self.dti {
+            Some(dti) => dti.name().to_string(),
+            None => format!("{:08x}", self.dti_hash),
+        }
+    }
+
+    pub fn file_ext(&self) -> String {
+        file_ext_for_dti_hash(self.dti_hash)
+    }
+
     pub fn quality(&self) -> u32 {
         self.quality
     }
+
+    pub fn size_compressed(&self) -> u32 {
+        self.size_compressed
+    }
+
+    pub fn size_uncompressed(&self) -> u32 {
+        self.size_uncompressed
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    fn end_offset(&self) -> u64 {
+        self.offset as u64 + self.size_compressed as u64
+    }
+}
+
+/// Decompressing stream over a single archive entry.
+///
+/// Only the compressed data is held in memory, the entry is inflated as it is
+/// read. Seeking forwards skips over decompressed data, seeking backwards
+/// restarts decompression from the beginning of the entry.
+pub struct ArchiveEntryReader {
+    decoder: ZlibDecoder<Cursor<Vec<u8>>>,
+    position: u64,
+    size: u64,
+}
+
+impl ArchiveEntryReader {
+    fn new(content_compressed: Vec<u8>, size: u64) -> Self {
+        Self {
+            decoder: ZlibDecoder::new(Cursor::new(content_compressed)),
+            position: 0,
+            size,
+        }
+    }
+
+    /// Uncompressed size of the entry, as stored in the archive
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn rewind_decoder(&mut self) {
+        let mut content_compressed = std::mem::take(self.decoder.get_mut());
+        content_compressed.set_position(0);
+
+        self.decoder.reset(content_compressed);
+        self.position = 0;
+    }
+}
+
+impl Read for ArchiveEntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_read = self.decoder.read(buf)?;
+        self.position += num_read as u64;
+
+        Ok(num_read)
+    }
+}
+
+impl Seek for ArchiveEntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        if target < self.position {
+            trace!("rewinding archive entry to seek to {:08x}", target);
+            self.rewind_decoder();
+        }
+
+        let num_skipped = std::io::copy(
+            &mut (&mut self.decoder).take(target - self.position),
+            &mut std::io::sink(),
+        )?;
+        self.position += num_skipped;
+
+        // Like a Cursor, seeking past the end is allowed, reads will just
+        // return nothing
+        self.position = target;
+
+        Ok(self.position)
+    }
+}
+
+/// Backing of archives opened with ArchiveFile::open_mapped. There are no
+/// values of it, entries are read from the mapping instead.
+pub enum Mapped {}
+
+impl Read for Mapped {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        match *self {}
+    }
+}
+
+impl Seek for Mapped {
+    fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+        match *self {}
+    }
+}
+
+enum ArchiveStorage<Backing: Read + Seek> {
+    Reader(Box<Mutex<Backing>>),
+    // Entries are read straight out of the mapping, without locking
+    Mapped(Mmap),
+}
+
+impl<Backing: Read + Seek> ArchiveStorage<Backing> {
+    fn read_at(&self, offset: u64, size: usize) -> anyhow::Result<Cow<'_, [u8]>> {
+        match self {
+            ArchiveStorage::Reader(reader) => {
+                let mut reader = reader.lock().unwrap();
+                reader.seek(SeekFrom::Start(offset))?;
+
+                let mut data = vec![0u8; size];
+                reader.read_exact(&mut data)?;
+
+                Ok(Cow::Owned(data))
+            }
+            ArchiveStorage::Mapped(map) => {
+                let data = usize::try_from(offset)
+                    .ok()
+                    .and_then(|offset| map.get(offset..offset.checked_add(size)?))
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "{} bytes at {:08x} is past the end of the archive ({} bytes)",
+                            size,
+                            offset,
+                            map.len()
+                        )
+                    })?;
+
+                Ok(Cow::Borrowed(data))
+            }
+        }
+    }
+
+    fn len(&self) -> anyhow::Result<u64> {
+        match self {
+            ArchiveStorage::Reader(reader) => Ok(reader.lock().unwrap().seek(SeekFrom::End(0))?),
+            ArchiveStorage::Mapped(map) => Ok(map.len() as u64),
+        }
+    }
+}
+
+struct ArchiveToc {
+    resources: Vec<ResourceInfo>,
+    index: HashMap<(ResourcePath, u32), usize>,
+    format: ArchiveFormat,
+    key: Option<ArchiveKey>,
 }
 
 pub struct ArchiveFile<Backing: Read + Seek> {
     resources: Vec<ResourceInfo>,
-    reader: Box<Mutex<Backing>>,
+    // (path, dti hash) -> index into resources
+    index: HashMap<(ResourcePath, u32), usize>,
+    format: ArchiveFormat,
+    key: Option<ArchiveKey>,
+    storage: ArchiveStorage<Backing>,
+}
+
+impl ArchiveFile<Mapped> {
+    /// Open an archive by memory mapping it. Entries are read from the mapping
+    /// directly, so they can be decompressed from several threads at once.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated by anything, including
+    /// other processes, while the archive is open. This is only suitable for
+    /// short lived handles, anything that keeps archives open should use
+    /// ArchiveFile::open. The tools in cli_util never write archives in
+    /// place, they write a new file and rename it over the old one.
+    pub unsafe fn open_mapped(
+        file: &File,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<Self> {
+        // SAFETY: the caller guarantees that the file isn't changed, and out
+        // of bounds entries are checked for in read_at
+        let map = unsafe { Mmap::map(file)? };
+
+        Self::from_storage(ArchiveStorage::Mapped(map), format, key)
+    }
 }
 
 impl<Backing: Read + Seek> ArchiveFile<Backing> {
-    pub fn new(mut reader: Backing) -> anyhow::Result<Self> {
-        let header: ArchiveHeader = util::read_struct(&mut reader)?;
+    // Returns the header with fields in native order, and the endianness
+    fn read_header<R: Read + Seek>(reader: &mut R) -> anyhow::Result<(ArchiveHeader, Endianness)> {
+        let header: ArchiveHeader = util::read_struct(reader)?;
 
         debug!("archive header: {:#?}", header);
 
-        assert_eq!({ header.magic }, ARCHIVE_MAGIC);
-        assert_eq!({ header.version }, ARCHIVE_VERSION);
+        let magic = header.magic;
+        let valid_magics = [ARCHIVE_MAGIC, ARCHIVE_MAGIC_ENCRYPTED];
+
+        if valid_magics.contains(&magic) {
+            Ok((header, Endianness::Little))
+        } else if valid_magics.contains(&magic.swap_bytes()) {
+            Ok((header.swap_bytes(), Endianness::Big))
+        } else {
+            Err(anyhow!("invalid archive magic {:08x}", { header.magic }))
+        }
+    }
+
+    /// Open an archive, detecting the format from the header
+    pub fn new(reader: Backing) -> anyhow::Result<Self> {
+        Self::open(reader, None, None)
+    }
+
+    /// Open an archive with a format that isn't in KNOWN_FORMATS
+    pub fn with_format(reader: Backing, format: ArchiveFormat) -> anyhow::Result<Self> {
+        Self::open(reader, Some(format), None)
+    }
+
+    /// Open an encrypted archive. The key is ignored if the archive isn't
+    /// encrypted.
+    pub fn with_key(reader: Backing, key: ArchiveKey) -> anyhow::Result<Self> {
+        Self::open(reader, None, Some(key))
+    }
+
+    pub fn open(
+        reader: Backing,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<Self> {
+        Self::from_storage(
+            ArchiveStorage::Reader(Box::from(Mutex::from(reader))),
+            format,
+            key,
+        )
+    }
+
+    fn from_storage(
+        storage: ArchiveStorage<Backing>,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<Self> {
+        let toc = match &storage {
+            ArchiveStorage::Reader(reader) => {
+                Self::read_toc(&mut *reader.lock().unwrap(), format, key)?
+            }
+            ArchiveStorage::Mapped(map) => Self::read_toc(&mut Cursor::new(&map[..]), format, key)?,
+        };
+
+        Ok(Self {
+            resources: toc.resources,
+            index: toc.index,
+            format: toc.format,
+            key: toc.key,
+            storage,
+        })
+    }
+
+    fn read_toc<R: Read + Seek>(
+        reader: &mut R,
+        format: Option<ArchiveFormat>,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<ArchiveToc> {
+        let (header, endianness) = Self::read_header(reader)?;
+
+        let key = if header.magic == ARCHIVE_MAGIC_ENCRYPTED {
+            Some(key.ok_or_else(|| anyhow!("archive is encrypted, but no key was given"))?)
+        } else {
+            None
+        };
+
+        let format = match format {
+            Some(format) => {
+                if header.version != format.version || endianness != format.endianness {
+                    return Err(anyhow!(
+                        "archive is version {} ({:?} endian), expected {:?}",
+                        { header.version },
+                        endianness,
+                        format
+                    ));
+                }
+
+                format
+            }
+            None => Self::detect_format(&header, endianness)?,
+        };
+
+        reader.seek(SeekFrom::Start(size_of::<ArchiveHeader>() as u64))?;
+        Self::read_resource_infos(reader, &header, format, key)
+    }
+
+    fn detect_format(
+        header: &ArchiveHeader,
+        endianness: Endianness,
+    ) -> anyhow::Result<ArchiveFormat> {
+        KNOWN_FORMATS
+            .iter()
+            .find(|format| format.version == header.version && format.endianness == endianness)
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "unknown archive version {} ({:?} endian)",
+                    { header.version },
+                    endianness
+                )
+            })
+    }
+
+    fn read_resource_infos<R: Read + Seek>(
+        reader: &mut R,
+        header: &ArchiveHeader,
+        format: ArchiveFormat,
+        key: Option<ArchiveKey>,
+    ) -> anyhow::Result<ArchiveToc> {
+        debug!("archive format: {:?} encrypted {}", format, key.is_some());
 
         let mut resources = vec![];
+        let mut index = HashMap::new();
+
+        for resource_idx in 0..header.num_resources as usize {
+            let raw_resource_info = RawResourceInfo::read(reader, &format, key.as_ref())?;
 
-        for _ in 0..header.num_resources {
-            let raw_resource_info: RawResourceInfo = util::read_struct(&mut reader)?;
+            // Not fatal here so that broken archives can still be verified
+            let (path, path_terminated) = match raw_resource_info.path.iter().position(|b| *b == 0)
+            {
+                Some(path_len) => (&raw_resource_info.path[..path_len], true),
+                None => (&raw_resource_info.path[..], false),
+            };
+            let path = String::from_utf8_lossy(path).to_string();
 
-            let path = CStr::from_bytes_until_nul(&raw_resource_info.path)?
-                .to_string_lossy()
-                .to_string();
+            if !path_terminated {
+                warn!("path for resource {:?} isn't null terminated", path);
+            }
 
-            let dti = DTI::from_hash(raw_resource_info.dti_type).unwrap();
+            let dti_hash = raw_resource_info.dti_type;
+            let dti = DTI::from_hash(dti_hash);
+            if dti.is_none() {
+                warn!("unknown DTI hash {:08x} for resource {:?}", dti_hash, path);
+            }
 
             let size_compressed = raw_resource_info.size_compressed;
             let size_uncompressed = raw_resource_info.bitfield_orgsize_quality & ORGSIZE_MASK;
@@ -96,184 +639,834 @@ impl<Backing: Read + Seek> ArchiveFile<Backing> {
 
             let offset = raw_resource_info.offset;
 
+            let resource = ResourceInfo {
+                path,
+                path_terminated,
+                dti_hash,
+                dti,
+                size_compressed,
+                size_uncompressed,
+                quality,
+                offset,
+            };
+
             trace!(
                 "resource: path {:?} dti {} size [c {} u {}] quality {} offset {:08x}",
-                path,
-                dti.name(),
+                resource.path,
+                resource.dti_name(),
                 size_compressed,
                 size_uncompressed,
                 quality,
                 offset
             );
 
-            resources.push(ResourceInfo {
-                path,
-                dti,
-                size_compressed,
-                size_uncompressed,
-                quality,
-                offset,
-            })
-        }
+            // Lookups by path get the first of any duplicates
+            match index.entry((ResourcePath::new(&resource.path), dti_hash)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(resource_idx);
+                }
+                Entry::Occupied(_) => warn!(
+                    "duplicate resource in archive: {:?} ({})",
+                    resource.path,
+                    resource.dti_name()
+                ),
+            }
+
+            resources.push(resource)
+        }
+
+        Ok(ArchiveToc {
+            resources,
+            index,
+            format,
+            key,
+        })
+    }
+
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn resource_infos(&self) -> &[ResourceInfo] {
+        &self.resources
+    }
+
+    pub fn resource_info(&self, path: &str, dti: &DTI) -> Option<&ResourceInfo> {
+        self.resource_info_by_hash(path, dti.hash())
+    }
+
+    /// Paths are compared ignoring case, like the game does
+    pub fn resource_info_by_hash(&self, path: &str, dti_hash: u32) -> Option<&ResourceInfo> {
+        self.index
+            .get(&(ResourcePath::new(path), dti_hash))
+            .map(|idx| &self.resources[*idx])
+    }
+
+    /// Read the entry the info describes, even if another entry has the same
+    /// path and type
+    pub fn get_resource_by_info(&self, info: &ResourceInfo) -> anyhow::Result<Option<Vec<u8>>> {
+        trace!("getting resource {:?}", info.path);
+
+        let content_compressed = self.get_resource_compressed(info)?;
+
+        let mut content_decompressed: Vec<u8> = Vec::with_capacity(info.size_uncompressed as usize);
+        let num_decompressed_bytes =
+            ZlibDecoder::new(&content_compressed[..]).read_to_end(&mut content_decompressed)?;
+
+        if num_decompressed_bytes != info.size_uncompressed as usize {
+            return Err(anyhow!(
+                "resource {:?} decompressed to {} bytes, expected {}",
+                info.path,
+                num_decompressed_bytes,
+                info.size_uncompressed
+            ));
+        }
+
+        Ok(Some(content_decompressed))
+    }
+
+    pub fn get_resource_with_path(
+        &self,
+        path: &Path,
+        dti: &DTI,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get_resource(ResourcePath::from(path).as_str(), dti)
+    }
+
+    pub fn get_resource(&self, path: &str, dti: &DTI) -> anyhow::Result<Option<Vec<u8>>> {
+        self.get_resource_by_hash(path, dti.hash())
+    }
+
+    pub fn get_resource_by_hash(
+        &self,
+        path: &str,
+        dti_hash: u32,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.resource_info_by_hash(path, dti_hash) {
+            Some(resource) => self.get_resource_by_info(resource),
+            None => Ok(None),
+        }
+    }
+
+    /// Open the entry the info describes, even if another entry has the same
+    /// path and type
+    pub fn open_resource_by_info(
+        &self,
+        info: &ResourceInfo,
+    ) -> anyhow::Result<Option<ArchiveEntryReader>> {
+        trace!("getting resource {:?}", info.path);
+
+        Ok(Some(ArchiveEntryReader::new(
+            self.get_resource_compressed(info)?.into_owned(),
+            info.size_uncompressed as u64,
+        )))
+    }
+
+    pub fn open_resource_with_path(
+        &self,
+        path: &Path,
+        dti: &DTI,
+    ) -> anyhow::Result<Option<ArchiveEntryReader>> {
+        self.open_resource(ResourcePath::from(path).as_str(), dti)
+    }
+
+    /// Open a stream over an entry, without decompressing all of it up front
+    pub fn open_resource(
+        &self,
+        path: &str,
+        dti: &DTI,
+    ) -> anyhow::Result<Option<ArchiveEntryReader>> {
+        self.open_resource_by_hash(path, dti.hash())
+    }
+
+    pub fn open_resource_by_hash(
+        &self,
+        path: &str,
+        dti_hash: u32,
+    ) -> anyhow::Result<Option<ArchiveEntryReader>> {
+        match self.resource_info_by_hash(path, dti_hash) {
+            Some(resource) => self.open_resource_by_info(resource),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the compressed data for an entry, exactly as it is stored in the
+    /// archive (after decryption). For mapped archives, this borrows from the
+    /// mapping unless the archive is encrypted.
+    pub fn get_resource_compressed(&self, info: &ResourceInfo) -> anyhow::Result<Cow<'_, [u8]>> {
+        let content_compressed = self
+            .storage
+            .read_at(info.offset as u64, info.size_compressed as usize)?;
+
+        if let Some(key) = &self.key {
+            let mut content_compressed = content_compressed.into_owned();
+            key.decrypt(&mut content_compressed);
+
+            return Ok(Cow::Owned(content_compressed));
+        }
+
+        Ok(content_compressed)
+    }
+
+    /// The bytes in the data area that don't belong to any entry, as (offset,
+    /// bytes), including anything after the last entry. Usually padding, but
+    /// not always zeroes.
+    pub fn gaps(&self) -> anyhow::Result<Vec<(u32, Vec<u8>)>> {
+        let archive_size = self.storage.len()?;
+
+        let mut by_offset: Vec<&ResourceInfo> = self.resources.iter().collect();
+        by_offset.sort_by_key(|info| info.offset);
+
+        let mut gaps = vec![];
+        let mut current_offset = self.format.data_start(self.resources.len()) as u64;
+        let ends = by_offset
+            .iter()
+            .map(|info| {
+                (
+                    info.offset as u64,
+                    info.offset as u64 + info.size_compressed as u64,
+                )
+            })
+            .chain(std::iter::once((archive_size, archive_size)));
+
+        for (start, end) in ends {
+            let start = start.min(archive_size);
+            if start > current_offset {
+                let data = self
+                    .storage
+                    .read_at(current_offset, (start - current_offset) as usize)?;
+                gaps.push((current_offset.try_into()?, data.into_owned()));
+            }
+
+            current_offset = current_offset.max(end);
+        }
+
+        Ok(gaps)
+    }
+
+    /// Check every entry in the archive for problems, decompressing all of
+    /// them. Returns a report for each entry, in table of contents order.
+    pub fn verify(&self) -> anyhow::Result<Vec<EntryReport>> {
+        let archive_size = self.storage.len()?;
+        let data_start = self.format.data_start(self.resources.len()) as u64;
+
+        let mut reports: Vec<EntryReport> = self
+            .resources
+            .iter()
+            .map(|info| EntryReport {
+                path: info.path.clone(),
+                dti_name: info.dti_name(),
+                problems: vec![],
+            })
+            .collect();
+
+        // Sort by offset, and keep track of the entry that reaches the
+        // furthest. An entry overlaps something if it starts before that one
+        // ends, even if its direct neighbour is short.
+        let mut by_offset: Vec<usize> = (0..self.resources.len()).collect();
+        by_offset.sort_by_key(|idx| self.resources[*idx].offset);
+
+        let mut furthest: Option<&ResourceInfo> = None;
+        for idx in by_offset {
+            let info = &self.resources[idx];
+
+            if let Some(furthest) = furthest {
+                // Identical data can be shared between several entries
+                let is_shared = furthest.offset == info.offset
+                    && furthest.size_compressed == info.size_compressed;
+
+                if !is_shared && furthest.end_offset() > info.offset as u64 {
+                    reports[idx].problems.push(EntryProblem::Overlaps {
+                        path: furthest.path.clone(),
+                        dti_name: furthest.dti_name(),
+                    });
+                }
+            }
+
+            if furthest.is_none_or(|furthest| info.end_offset() > furthest.end_offset()) {
+                furthest = Some(info);
+            }
+        }
+
+        for (info, report) in self.resources.iter().zip(reports.iter_mut()) {
+            if !info.path_terminated {
+                report.problems.push(EntryProblem::PathNotTerminated);
+            }
+
+            if (info.offset as u64) < data_start {
+                report.problems.push(EntryProblem::OverlapsHeader);
+            }
+
+            if info.end_offset() > archive_size {
+                report
+                    .problems
+                    .push(EntryProblem::OutOfBounds { archive_size });
+
+                // Nothing to decompress
+                continue;
+            }
+
+            let content_compressed = self.get_resource_compressed(info)?;
+            let mut decoder = ZlibDecoder::new(&content_compressed[..]);
+
+            match std::io::copy(&mut decoder, &mut std::io::sink()) {
+                Ok(num_decompressed_bytes)
+                    if num_decompressed_bytes != info.size_uncompressed as u64 =>
+                {
+                    report.problems.push(EntryProblem::SizeMismatch {
+                        expected: info.size_uncompressed,
+                        actual: num_decompressed_bytes,
+                    })
+                }
+                Ok(_) => {}
+                Err(err) => report
+                    .problems
+                    .push(EntryProblem::Decompression(err.to_string())),
+            }
+        }
+
+        Ok(reports)
+    }
+}
+
+#[derive(Debug)]
+pub enum EntryProblem {
+    PathNotTerminated,
+    OverlapsHeader,
+    Overlaps { path: String, dti_name: String },
+    OutOfBounds { archive_size: u64 },
+    Decompression(String),
+    SizeMismatch { expected: u32, actual: u64 },
+}
+
+impl Display for EntryProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryProblem::PathNotTerminated => write!(f, "path isn't null terminated"),
+            EntryProblem::OverlapsHeader => write!(f, "data overlaps the table of contents"),
+            EntryProblem::Overlaps { path, dti_name } => {
+                write!(f, "data overlaps {:?} ({})", path, dti_name)
+            }
+            EntryProblem::OutOfBounds { archive_size } => {
+                write!(
+                    f,
+                    "data is past the end of the archive ({} bytes)",
+                    archive_size
+                )
+            }
+            EntryProblem::Decompression(err) => write!(f, "failed to decompress: {}", err),
+            EntryProblem::SizeMismatch { expected, actual } => {
+                write!(f, "decompressed to {} bytes, expected {}", actual, expected)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EntryReport {
+    path: String,
+    dti_name: String,
+    problems: Vec<EntryProblem>,
+}
+
+impl EntryReport {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn dti_name(&self) -> &str {
+        &self.dti_name
+    }
+
+    pub fn problems(&self) -> &[EntryProblem] {
+        &self.problems
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ResourceDataForWrite {
+    Uncompressed(Vec<u8>),
+    // Copied through to the archive as is
+    Compressed {
+        data: Vec<u8>,
+        size_uncompressed: u32,
+    },
+}
+
+// TODO: can this be combined with ResourceInfo
+struct ArchiveResourceForWrite {
+    path: String,
+    quality: u32,
+
+    data: ResourceDataForWrite,
+    dti_hash: u32,
+
+    // Where the data was in the archive this was unpacked from, if it should
+    // be kept there
+    original_offset: Option<u32>,
+    // zlib level for uncompressed data, instead of the writer's
+    compression: Option<Compression>,
+}
+
+impl ArchiveResourceForWrite {
+    fn size_uncompressed(&self) -> usize {
+        match &self.data {
+            ResourceDataForWrite::Uncompressed(data) => data.len(),
+            ResourceDataForWrite::Compressed {
+                size_uncompressed, ..
+            } => *size_uncompressed as usize,
+        }
+    }
+}
+
+pub struct ArchiveWriter {
+    resources: Vec<ArchiveResourceForWrite>,
+    format: ArchiveFormat,
+    key: Option<ArchiveKey>,
+    compression: Compression,
+    // Size of the original archive, the output is padded up to this
+    original_size: Option<u32>,
+    // Bytes between the data in the original archive, as (offset, bytes).
+    // Padding is filled from these where it lines up, and zeroes otherwise.
+    original_gaps: Vec<(u32, Vec<u8>)>,
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        ArchiveWriter {
+            resources: vec![],
+            format: ArchiveFormat::default(),
+            key: None,
+            compression: Compression::default(),
+            original_size: None,
+            original_gaps: vec![],
+        }
+    }
+
+    /// Create a writer holding all of the resources in an existing archive, for
+    /// editing it. The compressed data is copied through as is, and stays at
+    /// the same offset unless something before it grows.
+    pub fn from_archive<Backing: Read + Seek>(
+        archive: &ArchiveFile<Backing>,
+    ) -> anyhow::Result<Self> {
+        let mut writer = Self::new();
+        writer.set_format(archive.format());
+        writer.set_key(archive.key.clone());
+        writer.set_original_gaps(archive.gaps()?);
+
+        for info in archive.resource_infos() {
+            writer.push_resource(
+                &info.path,
+                info.dti_hash,
+                info.quality,
+                ResourceDataForWrite::Compressed {
+                    data: archive.get_resource_compressed(info)?.into_owned(),
+                    size_uncompressed: info.size_uncompressed,
+                },
+                Some(info.offset),
+            );
+        }
+
+        Ok(writer)
+    }
+
+    pub fn set_format(&mut self, format: ArchiveFormat) {
+        self.format = format;
+    }
+
+    /// Write an encrypted archive if a key is set
+    pub fn set_key(&mut self, key: Option<ArchiveKey>) {
+        self.key = key;
+    }
+
+    /// zlib level used for data added uncompressed
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// zlib level for a single resource added uncompressed, instead of the
+    /// one set with set_compression. Resources with identical data are only
+    /// stored once, with the level of the first of them.
+    pub fn set_file_compression(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+        compression: Compression,
+    ) -> anyhow::Result<()> {
+        self.find_resource_mut(path, dti_hash)?.compression = Some(compression);
+
+        Ok(())
+    }
+
+    /// Pad the archive up to the size of the original file
+    pub fn set_original_size(&mut self, original_size: u32) {
+        self.original_size = Some(original_size);
+    }
+
+    /// Fill padding with the bytes that were there in the original archive,
+    /// see ArchiveFile::gaps
+    pub fn set_original_gaps(&mut self, original_gaps: Vec<(u32, Vec<u8>)>) {
+        self.original_gaps = original_gaps;
+    }
+
+    pub fn add_file(
+        &mut self,
+        path: &str,
+        dti: &'static DTI,
+        quality: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.add_file_with_hash(path, dti.hash(), quality, data)
+    }
+
+    /// Like add_file, but also allows types which aren't in the DTI table
+    pub fn add_file_with_hash(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+        quality: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.push_resource(
+            path,
+            dti_hash,
+            quality,
+            ResourceDataForWrite::Uncompressed(data.to_vec()),
+            None,
+        );
+
+        Ok(())
+    }
+
+    /// Add data that is already zlib compressed, eg. from another archive.
+    /// It will be written out without being recompressed.
+    pub fn add_compressed_file_with_hash(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+        quality: u32,
+        size_uncompressed: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.push_resource(
+            path,
+            dti_hash,
+            quality,
+            ResourceDataForWrite::Compressed {
+                data: data.to_vec(),
+                size_uncompressed,
+            },
+            None,
+        );
+
+        Ok(())
+    }
+
+    fn push_resource(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+        quality: u32,
+        data: ResourceDataForWrite,
+        original_offset: Option<u32>,
+    ) -> &mut ArchiveResourceForWrite {
+        self.resources.push(ArchiveResourceForWrite {
+            path: path.to_string(),
+            quality,
+            dti_hash,
+
+            data,
+            original_offset,
+            compression: None,
+        });
 
-        Ok(Self {
-            resources,
-            reader: Box::from(Mutex::from(reader)),
-        })
+        self.resources.last_mut().unwrap()
     }
 
-    pub fn resource_infos(&self) -> &[ResourceInfo] {
-        &self.resources
+    /// Keep the data for a resource at the offset it had in the original
+    /// archive. Resources that have an original offset are written in offset
+    /// order, and only get moved if something before them grew.
+    pub fn set_original_offset(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+        offset: u32,
+    ) -> anyhow::Result<()> {
+        self.find_resource_mut(path, dti_hash)?.original_offset = Some(offset);
+
+        Ok(())
     }
 
-    pub fn get_resource_by_info(&self, info: &ResourceInfo) -> anyhow::Result<Option<Vec<u8>>> {
-        self.get_resource(&info.path, info.dti)
+    pub fn contains_file(&self, path: &str, dti_hash: u32) -> bool {
+        self.find_resource_idx(path, dti_hash).is_ok()
     }
 
-    pub fn get_resource_with_path(
-        &self,
-        path: &Path,
-        dti: &DTI,
-    ) -> anyhow::Result<Option<Vec<u8>>> {
-        let path = path.to_string_lossy().replace("/", "\\");
+    /// Replace the contents of a resource, keeping its position in the archive
+    pub fn replace_file(&mut self, path: &str, dti_hash: u32, data: &[u8]) -> anyhow::Result<()> {
+        self.find_resource_mut(path, dti_hash)?.data =
+            ResourceDataForWrite::Uncompressed(data.to_vec());
 
-        self.get_resource(&path, dti)
+        Ok(())
     }
 
-    pub fn get_resource(&self, path: &str, dti: &DTI) -> anyhow::Result<Option<Vec<u8>>> {
-        trace!("getting resource {:?}", path);
+    pub fn remove_file(&mut self, path: &str, dti_hash: u32) -> anyhow::Result<()> {
+        let idx = self.find_resource_idx(path, dti_hash)?;
+        self.resources.remove(idx);
 
-        // hashmaps make everything go fast...
-        let resource = self
-            .resources
-            .iter()
-            .find(|resource| (resource.path == path) && (resource.dti == dti));
+        Ok(())
+    }
 
-        let resource = if let Some(resource) = resource {
-            resource
-        } else {
-            return Ok(None);
-        };
+    pub fn rename_file(&mut self, path: &str, dti_hash: u32, new_path: &str) -> anyhow::Result<()> {
+        // Only changing the case of the path is fine
+        if ResourcePath::new(new_path) != ResourcePath::new(path)
+            && self.contains_file(new_path, dti_hash)
+        {
+            return Err(anyhow!(
+                "resource {:?} ({:08x}) already exists",
+                new_path,
+                dti_hash
+            ));
+        }
 
-        let mut reader = self.reader.lock().unwrap();
+        self.find_resource_mut(path, dti_hash)?.path = new_path.to_string();
 
-        reader.seek(std::io::SeekFrom::Start(resource.offset as u64))?;
+        Ok(())
+    }
 
-        let mut content_compressed = vec![0u8; resource.size_compressed as usize];
-        reader.read_exact(&mut content_compressed)?;
+    // Paths are compared ignoring case, like ArchiveFile lookups
+    fn find_resource_idx(&self, path: &str, dti_hash: u32) -> anyhow::Result<usize> {
+        let path = ResourcePath::new(path);
 
-        drop(reader);
+        self.resources
+            .iter()
+            .position(|resource| {
+                resource.dti_hash == dti_hash && ResourcePath::new(&resource.path) == path
+            })
+            .ok_or_else(|| anyhow!("no resource {:?} ({:08x}) in writer", path, dti_hash))
+    }
 
-        let mut cursor = Cursor::new(&content_compressed);
-        let mut decoder = ZlibDecoder::new(&mut cursor);
+    fn find_resource_mut(
+        &mut self,
+        path: &str,
+        dti_hash: u32,
+    ) -> anyhow::Result<&mut ArchiveResourceForWrite> {
+        let idx = self.find_resource_idx(path, dti_hash)?;
 
-        let mut content_decompressed: Vec<u8> = vec![];
-        let num_decompressed_bytes = decoder.read_to_end(&mut content_decompressed)?;
+        Ok(&mut self.resources[idx])
+    }
 
-        assert_eq!(num_decompressed_bytes, resource.size_uncompressed as usize);
+    // Groups resources with identical data, so it only gets compressed and
+    // stored once. Returns the data for each group, and the group of each
+    // resource.
+    fn shared_datas(&self) -> (Vec<&ResourceDataForWrite>, Vec<usize>) {
+        let mut datas = vec![];
+        let mut resource_data_idxs = vec![];
+
+        // Resources that came from an archive only share data if it was
+        // already shared there, so the original layout is kept
+        let mut by_original_offset: HashMap<(Option<u32>, &ResourceDataForWrite), usize> =
+            HashMap::new();
+        let mut by_data: HashMap<&ResourceDataForWrite, usize> = HashMap::new();
+
+        for resource in &self.resources {
+            let existing_idx = match resource.original_offset {
+                Some(_) => by_original_offset.get(&(resource.original_offset, &resource.data)),
+                None => by_data.get(&resource.data),
+            };
 
-        Ok(Some(content_decompressed))
-    }
-}
+            let data_idx = match existing_idx {
+                Some(data_idx) => *data_idx,
+                None => {
+                    let data_idx = datas.len();
+                    datas.push(&resource.data);
 
-// TODO: can this be combined with ResourceInfo
-struct ArchiveResourceForWrite {
-    path: String,
-    quality: u32,
+                    by_original_offset.insert((resource.original_offset, &resource.data), data_idx);
+                    by_data.entry(&resource.data).or_insert(data_idx);
 
-    data: Vec<u8>,
-    dti: &'static DTI,
-}
+                    data_idx
+                }
+            };
 
-pub struct ArchiveWriter {
-    resources: Vec<ArchiveResourceForWrite>,
-}
+            resource_data_idxs.push(data_idx);
+        }
 
-impl ArchiveWriter {
-    pub fn new() -> Self {
-        ArchiveWriter { resources: vec![] }
+        (datas, resource_data_idxs)
     }
 
-    pub fn add_file(
-        &mut self,
-        path: &str,
-        dti: &'static DTI,
-        quality: u32,
-        data: &[u8],
-    ) -> anyhow::Result<()> {
-        self.resources.push(ArchiveResourceForWrite {
-            path: path.to_string(),
-            quality,
-            dti,
+    // Returns the offset for each piece of data, given the original offset of
+    // the resources they belong to
+    fn data_offsets(
+        start_offset: u32,
+        original_offsets: &[Option<u32>],
+        compressed_datas: &[Cow<[u8]>],
+    ) -> anyhow::Result<Vec<u32>> {
+        let mut order: Vec<usize> = (0..compressed_datas.len()).collect();
+
+        // Data without an original offset goes after everything else, in the
+        // order it was added
+        order.sort_by_key(|idx| (original_offsets[*idx].is_none(), original_offsets[*idx]));
+
+        let mut offsets = vec![0; compressed_datas.len()];
+        let mut current_offset = start_offset;
+        for idx in order {
+            let offset = original_offsets[idx].unwrap_or(0).max(current_offset);
+
+            offsets[idx] = offset;
+            current_offset = u32::try_from(compressed_datas[idx].len())
+                .ok()
+                .and_then(|len| offset.checked_add(len))
+                .ok_or_else(|| anyhow!("archive data doesn't fit in 32 bit offsets"))?;
+        }
 
-            data: data.to_vec(),
-        });
+        Ok(offsets)
+    }
+
+    // Padding from start to end, with the original bytes where there are any
+    fn write_padding<W: Write>(&self, writer: &mut W, start: u32, end: u32) -> anyhow::Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+
+        let mut padding = vec![0u8; (end - start) as usize];
+        for (gap_offset, gap) in &self.original_gaps {
+            let gap_end = *gap_offset + gap.len() as u32;
+            let overlap_start = start.max(*gap_offset);
+            let overlap_end = end.min(gap_end);
+
+            if overlap_start < overlap_end {
+                padding[(overlap_start - start) as usize..(overlap_end - start) as usize]
+                    .copy_from_slice(
+                        &gap[(overlap_start - gap_offset) as usize
+                            ..(overlap_end - gap_offset) as usize],
+                    );
+            }
+        }
+
+        writer.write_all(&padding)?;
 
         Ok(())
     }
 
     pub fn save<W: Write>(&self, writer: &mut W) -> anyhow::Result<()> {
         let header = ArchiveHeader {
-            magic: ARCHIVE_MAGIC,
-            version: ARCHIVE_VERSION,
+            magic: match self.key {
+                Some(_) => ARCHIVE_MAGIC_ENCRYPTED,
+                None => ARCHIVE_MAGIC,
+            },
+            version: self.format.version,
             num_resources: self.resources.len().try_into().unwrap(),
         };
+        let header = match self.format.endianness {
+            Endianness::Little => header,
+            Endianness::Big => header.swap_bytes(),
+        };
 
         writer.write_all(header.as_bytes())?;
 
-        let start_offset =
-            size_of::<ArchiveHeader>() + (self.resources.len() * size_of::<RawResourceInfo>());
-        let mut offset: u32 = start_offset.try_into().unwrap();
+        let start_offset: u32 = self
+            .format
+            .data_start(self.resources.len())
+            .try_into()
+            .unwrap();
+
+        let (datas, resource_data_idxs) = self.shared_datas();
+        debug!(
+            "writing {} resources, {} unique",
+            self.resources.len(),
+            datas.len()
+        );
+
+        // The first resource to use each piece of data decides how it's
+        // compressed and where it goes
+        let mut compressions = vec![None; datas.len()];
+        let mut original_offsets = vec![None; datas.len()];
+        for (resource, data_idx) in self.resources.iter().zip(&resource_data_idxs).rev() {
+            compressions[*data_idx] = resource.compression;
+            original_offsets[*data_idx] = resource.original_offset;
+        }
 
-        let compressed_datas: Vec<Vec<u8>> = self
-            .resources
+        let compressed_datas: Vec<Cow<[u8]>> = datas
             .par_iter()
-            .map(|resource| {
-                let data: &[u8] = &resource.data;
-                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default()); // TODO: make level configurable
-                encoder.write_all(data)?;
-
-                Ok(encoder.finish()?)
+            .zip(compressions.par_iter())
+            .map(|(data, compression)| match data {
+                ResourceDataForWrite::Uncompressed(data) => {
+                    let mut encoder =
+                        ZlibEncoder::new(Vec::new(), compression.unwrap_or(self.compression));
+                    encoder.write_all(data)?;
+
+                    Ok(Cow::Owned(encoder.finish()?))
+                }
+                ResourceDataForWrite::Compressed { data, .. } => Ok(Cow::Borrowed(&data[..])),
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
 
-        for (resource, compressed_data) in self.resources.iter().zip(&compressed_datas) {
+        let offsets = Self::data_offsets(start_offset, &original_offsets, &compressed_datas)?;
+
+        for (resource, data_idx) in self.resources.iter().zip(&resource_data_idxs) {
+            let compressed_data = &compressed_datas[*data_idx];
+            let offset = &offsets[*data_idx];
+            let size_uncompressed = resource.size_uncompressed();
+
             trace!(
-                "writing resource info: path {} comp {} unc {} quality {} dti {}",
+                "writing resource info: path {} comp {} unc {} quality {} dti {:08x} offset {:08x}",
                 resource.path,
                 compressed_data.len(),
-                resource.data.len(),
+                size_uncompressed,
                 resource.quality,
-                resource.dti.name()
+                resource.dti_hash,
+                offset
             );
 
-            assert!(ORGSIZE_MASK >= resource.data.len().try_into().unwrap());
+            assert!(ORGSIZE_MASK >= size_uncompressed.try_into().unwrap());
             assert!(resource.quality <= QUALITY_MASK);
 
-            let bitfield_orgsize_quality = (resource.data.len() as u32 & ORGSIZE_MASK)
+            let bitfield_orgsize_quality = (size_uncompressed as u32 & ORGSIZE_MASK)
                 | ((resource.quality & QUALITY_MASK) << 29);
 
             let mut path_bytes = resource.path.as_bytes().to_vec();
-            assert!(path_bytes.len() <= PATH_MAXLEN);
+            assert!(path_bytes.len() < self.format.path_size);
 
-            path_bytes.resize(PATH_MAXLEN + 1, 0);
+            path_bytes.resize(self.format.path_size, 0);
 
-            let size_compressed = compressed_data.len().try_into().unwrap();
             let info = RawResourceInfo {
-                path: path_bytes.try_into().unwrap(),
-                dti_type: resource.dti.hash(),
-                size_compressed,
+                path: path_bytes,
+                dti_type: resource.dti_hash,
+                size_compressed: compressed_data.len().try_into().unwrap(),
                 bitfield_orgsize_quality,
-                offset,
+                offset: *offset,
             };
 
-            offset += size_compressed;
+            info.write(writer, &self.format, self.key.as_ref())?;
+        }
+
+        let mut data_order: Vec<usize> = (0..datas.len()).collect();
+        data_order.sort_by_key(|idx| offsets[*idx]);
 
-            writer.write_all(info.as_bytes())?;
+        let mut current_offset = start_offset;
+        for idx in data_order {
+            self.write_padding(writer, current_offset, offsets[idx])?;
+
+            if let Some(key) = &self.key {
+                let mut encrypted_data = compressed_datas[idx].to_vec();
+                key.encrypt(&mut encrypted_data);
+
+                writer.write_all(&encrypted_data)?;
+            } else {
+                writer.write_all(&compressed_datas[idx])?;
+            }
+            current_offset = offsets[idx] + compressed_datas[idx].len() as u32;
         }
 
-        for data in &compressed_datas {
-            writer.write_all(&data)?;
+        if let Some(original_size) = self.original_size {
+            self.write_padding(writer, current_offset, original_size)?;
         }
 
         Ok(())
@@ -283,80 +1476,491 @@ impl ArchiveWriter {
 pub mod cli_util {
     use std::path::{Path, PathBuf};
 
-    use log::debug;
+    use anyhow::anyhow;
+    use flate2::Compression;
+    use log::{debug, warn};
+    use rayon::prelude::*;
 
-    use crate::DTI;
+    use crate::{
+        archive_diff::{self, EntryChange},
+        hash_dictionary::{HashDictionary, HashKind},
+        resource_path::ResourcePath,
+        DTI,
+    };
 
-    use super::{ArchiveFile, ArchiveWriter};
+    use super::{
+        file_ext_for_dti_hash, ArchiveFile, ArchiveFormat, ArchiveKey, ArchiveWriter,
+        ResourceDataForWrite, ResourceInfo,
+    };
 
     const FILE_INFO_PATH_NAME: &str = "info.json";
     #[derive(serde::Serialize, serde::Deserialize)]
     struct FileInfo {
         path: String,
-        dti: String,
+        // Only one of these is written, dti_hash is used for types that
+        // aren't in the DTI table
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dti: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dti_hash: Option<u32>,
         quality: u32,
+        // Offset of the data in the source archive
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u32>,
+        // Guessed from the zlib header, used if the file was modified. Can be
+        // changed by hand.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression_level: Option<u32>,
+    }
+
+    impl FileInfo {
+        fn dti_hash(&self) -> anyhow::Result<u32> {
+            match (&self.dti, self.dti_hash) {
+                (Some(dti), _) => Ok(DTI::from_str(dti)
+                    .ok_or_else(|| anyhow!("invalid dti: {}", dti))?
+                    .hash()),
+                (None, Some(dti_hash)) => Ok(dti_hash),
+                (None, None) => Err(anyhow!("no dti for file {:?}", self.path)),
+            }
+        }
     }
 
-    pub fn unpack_archive(archive_path: &Path, out_dir: &Path) -> anyhow::Result<()> {
-        let file = Box::new(std::fs::File::open(&archive_path)?);
-        let archive = ArchiveFile::new(file)?;
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct ArchiveInfo {
+        // The archive that was unpacked. When repacking, files that weren't
+        // modified have their compressed data copied from it as is
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source_archive: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source_size: Option<u32>,
+        // Guessed from the first entry, used for modified files that don't
+        // have their own level
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression_level: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<ArchiveFormat>,
+        // The key isn't stored, it has to be given again when repacking
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        encrypted: bool,
+
+        files: Vec<FileInfo>,
+    }
 
-        let mut file_infos = vec![];
+    // Older info.json files only have the list of files
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum ArchiveInfoCompat {
+        Archive(ArchiveInfo),
+        Files(Vec<FileInfo>),
+    }
 
-        for resource in archive.resource_infos() {
-            debug!(
-                "Extracting {:?} ({})",
-                resource.path(),
-                resource.dti().name()
-            );
+    // The zlib header only stores a rough level, map it back to the level
+    // zlib would have used
+    fn guess_compression_level(data_compressed: &[u8]) -> Option<u32> {
+        let flags = data_compressed.get(1)?;
 
-            let data = archive.get_resource_by_info(resource)?.unwrap();
-            let out_path = out_dir.join(
-                PathBuf::from(resource.path().replace("\\", "/"))
-                    .with_extension(resource.dti().file_ext().expect("DTI doesn't have an ext")),
-            );
+        Some(match flags >> 6 {
+            0 => 1,
+            1 => 5,
+            2 => 6,
+            _ => 9,
+        })
+    }
+
+    pub fn unpack_archive(
+        archive_path: &Path,
+        out_dir: &Path,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let file = std::fs::File::open(archive_path)?;
+        let source_size = file.metadata()?.len().try_into().ok();
+        // SAFETY: the archive is only open while it's being unpacked
+        let archive = unsafe { ArchiveFile::open_mapped(&file, format, key.cloned())? };
+
+        archive
+            .resource_infos()
+            .par_iter()
+            .try_for_each(|resource| -> anyhow::Result<()> {
+                debug!("Extracting {:?} ({})", resource.path(), resource.dti_name());
+
+                let data = archive.get_resource_by_info(resource)?.unwrap();
+                let out_path = out_dir.join(
+                    ResourcePath::new(resource.path())
+                        .to_path_buf()
+                        .with_extension(resource.file_ext()),
+                );
+
+                std::fs::create_dir_all(out_path.parent().unwrap())?;
+                std::fs::write(out_path, data)?;
 
-            std::fs::create_dir_all(out_path.parent().unwrap())?;
-            std::fs::write(out_path, data)?;
+                Ok(())
+            })?;
 
+        let mut file_infos = vec![];
+        for resource in archive.resource_infos() {
             file_infos.push(FileInfo {
                 path: resource.path().to_string(),
-                dti: resource.dti().name().to_string(),
+                dti: resource.dti().map(|dti| dti.name().to_string()),
+                dti_hash: match resource.dti() {
+                    Some(_) => None,
+                    None => Some(resource.dti_hash()),
+                },
                 quality: resource.quality(),
+                offset: Some(resource.offset()),
+                compression_level: guess_compression_level(
+                    &archive.get_resource_compressed(resource)?,
+                ),
             });
         }
 
+        let archive_info = ArchiveInfo {
+            source_archive: Some(archive_path.canonicalize()?),
+            source_size,
+            compression_level: file_infos.first().and_then(|info| info.compression_level),
+            format: Some(archive.format()),
+            encrypted: archive.is_encrypted(),
+            files: file_infos,
+        };
+
         std::fs::write(
             out_dir.join(FILE_INFO_PATH_NAME),
-            serde_json::to_string_pretty(&file_infos)?.as_bytes(),
+            serde_json::to_string_pretty(&archive_info)?.as_bytes(),
         )?;
 
         Ok(())
     }
 
-    pub fn repack_archive(archive_path: &Path) -> anyhow::Result<()> {
-        let file_infos: Vec<FileInfo> = serde_json::from_reader(std::fs::File::open(
+    /// Pack an unpacked archive back up. If keep_original is set, files that
+    /// are unchanged from the source archive are copied through without being
+    /// recompressed, and the original data layout is kept. The format is only
+    /// needed for info.json files that don't have one.
+    pub fn repack_archive(
+        archive_path: &Path,
+        out_path: &Path,
+        keep_original: bool,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let archive_info: ArchiveInfoCompat = serde_json::from_reader(std::fs::File::open(
             &archive_path.join(FILE_INFO_PATH_NAME),
         )?)?;
+        let archive_info = match archive_info {
+            ArchiveInfoCompat::Archive(archive_info) => archive_info,
+            ArchiveInfoCompat::Files(files) => ArchiveInfo {
+                source_archive: None,
+                source_size: None,
+                compression_level: None,
+                format: None,
+                encrypted: false,
+                files,
+            },
+        };
+        let format = archive_info.format.or(format);
+
+        let source_archive = match &archive_info.source_archive {
+            Some(source_path) if keep_original => match std::fs::File::open(source_path) {
+                Ok(file) => Some(ArchiveFile::open(file, format, key.cloned())?),
+                Err(err) => {
+                    warn!(
+                        "couldn't open source archive {:?}, recompressing everything: {}",
+                        source_path, err
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
 
-        let mut out_file = std::fs::File::create(&PathBuf::from("test.arc"))?;
         let mut archive_writer = ArchiveWriter::new();
 
-        for info in file_infos.iter() {
-            let dti = DTI::from_str(&info.dti).expect("invalid dti");
+        if let Some(format) = format {
+            archive_writer.set_format(format);
+        }
+
+        if archive_info.encrypted {
+            let key = key.ok_or_else(|| anyhow!("archive was encrypted, a key is needed"))?;
+            archive_writer.set_key(Some(key.clone()));
+        }
+
+        if let Some(level) = archive_info.compression_level {
+            archive_writer.set_compression(Compression::new(level));
+        }
+
+        for info in archive_info.files.iter() {
+            let dti_hash = info.dti_hash()?;
 
             let fs_path = archive_path
-                .join(info.path.replace("\\", "/"))
-                .with_extension(dti.file_ext().expect("dti doesn't have file ext"));
+                .join(ResourcePath::new(&info.path).to_path_buf())
+                .with_extension(file_ext_for_dti_hash(dti_hash));
 
             let data = std::fs::read(fs_path)?;
 
-            archive_writer.add_file(&info.path, dti, info.quality, &data)?;
+            let original = source_archive.as_ref().and_then(|source_archive| {
+                Some((
+                    source_archive,
+                    source_archive.resource_info_by_hash(&info.path, dti_hash)?,
+                ))
+            });
+
+            // Only copy the data through if it's exactly the same as the original
+            let unmodified_original = match original {
+                Some((source_archive, original))
+                    if original.size_uncompressed() as usize == data.len() =>
+                {
+                    let original_data = source_archive.get_resource_by_info(original)?;
+                    (original_data.as_ref() == Some(&data)).then_some((source_archive, original))
+                }
+                _ => None,
+            };
+
+            let original_offset = info.offset.filter(|_| source_archive.is_some());
+
+            let data = if let Some((source_archive, original)) = unmodified_original {
+                ResourceDataForWrite::Compressed {
+                    data: source_archive
+                        .get_resource_compressed(original)?
+                        .into_owned(),
+                    size_uncompressed: original.size_uncompressed(),
+                }
+            } else {
+                debug!("{:?} was modified, recompressing", info.path);
+                ResourceDataForWrite::Uncompressed(data)
+            };
+
+            // Looking the entry up afterwards to set its offset and level
+            // would be quadratic in the number of entries
+            archive_writer
+                .push_resource(&info.path, dti_hash, info.quality, data, original_offset)
+                .compression = info.compression_level.map(Compression::new);
+        }
+
+        if let Some(source_size) = archive_info
+            .source_size
+            .filter(|_| source_archive.is_some())
+        {
+            archive_writer.set_original_size(source_size);
+        }
+
+        if let Some(source_archive) = &source_archive {
+            archive_writer.set_original_gaps(source_archive.gaps()?);
         }
 
+        save_archive(&archive_writer, out_path)
+    }
+
+    // Writes to a temporary file first, so that the output can be the archive
+    // that is being read from
+    fn save_archive(archive_writer: &ArchiveWriter, out_path: &Path) -> anyhow::Result<()> {
+        let temp_path = out_path.with_extension("arc.tmp");
+
+        let mut out_file = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
         archive_writer.save(&mut out_file)?;
+        // Anything that fails to get written must not replace the archive
+        out_file.into_inner()?.sync_all()?;
+
+        std::fs::rename(&temp_path, out_path)?;
+
+        Ok(())
+    }
+
+    /// Print every entry in an archive, in table of contents order. DTI hashes
+    /// that aren't in the DTI table are resolved with the dictionary if it has
+    /// a name for them.
+    pub fn list_archive(
+        archive_path: &Path,
+        dictionary: &HashDictionary,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let archive = ArchiveFile::open(std::fs::File::open(archive_path)?, format, key.cloned())?;
+
+        for info in archive.resource_infos() {
+            let dti_name = match info.dti() {
+                Some(dti) => dti.name().to_string(),
+                None => match dictionary.resolve(HashKind::dti, info.dti_hash()) {
+                    [] => format!("{:08x}", info.dti_hash()),
+                    names => format!("{:08x} {}?", info.dti_hash(), names.join("|")),
+                },
+            };
+
+            println!(
+                "{:08x} {:>10} {:>10} {} {:?} ({})",
+                info.offset(),
+                info.size_compressed(),
+                info.size_uncompressed(),
+                info.quality(),
+                info.path(),
+                dti_name
+            );
+        }
+
+        println!("{} entries", archive.resource_infos().len());
+
+        Ok(())
+    }
+
+    /// Print a report for every entry in an archive, returning an error if any
+    /// of them have problems
+    pub fn verify_archive(
+        archive_path: &Path,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let archive = ArchiveFile::open(std::fs::File::open(archive_path)?, format, key.cloned())?;
+        let reports = archive.verify()?;
+
+        let mut num_bad_entries = 0;
+        for report in &reports {
+            if report.is_ok() {
+                println!("OK   {:?} ({})", report.path(), report.dti_name());
+                continue;
+            }
+
+            num_bad_entries += 1;
+            println!("FAIL {:?} ({})", report.path(), report.dti_name());
+            for problem in report.problems() {
+                println!("     {}", problem);
+            }
+        }
+
+        if num_bad_entries != 0 {
+            return Err(anyhow!(
+                "{} of {} entries have problems",
+                num_bad_entries,
+                reports.len()
+            ));
+        }
+
+        println!("all {} entries OK", reports.len());
+
+        Ok(())
+    }
+
+    /// Print the changes between two archives. With format_aware set, entries
+    /// that can be parsed also get a diff of their contents.
+    pub fn diff_archive(
+        path_a: &Path,
+        path_b: &Path,
+        format_aware: bool,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let archive_a = ArchiveFile::open(std::fs::File::open(path_a)?, format, key.cloned())?;
+        let archive_b = ArchiveFile::open(std::fs::File::open(path_b)?, format, key.cloned())?;
+
+        let changes = archive_diff::diff_archives(&archive_a, &archive_b)?;
+
+        for change in &changes {
+            println!("{}", change);
+
+            let EntryChange::Modified { path, dti_hash, .. } = change else {
+                continue;
+            };
+
+            if !format_aware {
+                continue;
+            }
+
+            let data_a = archive_a.get_resource_by_hash(path, *dti_hash)?.unwrap();
+            let data_b = archive_b.get_resource_by_hash(path, *dti_hash)?.unwrap();
+
+            match archive_diff::diff_resource_contents(&data_a, &data_b) {
+                Ok(Some(content_diff)) => {
+                    for line in content_diff.lines() {
+                        println!("    {}", line);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => warn!("couldn't diff contents of {:?}: {}", path, err),
+            }
+        }
+
+        println!("{} changed entries", changes.len());
+
         Ok(())
     }
+
+    /// Parse a DTI name, or a hex hash for types that aren't in the DTI table
+    pub fn parse_dti_hash(dti: &str) -> anyhow::Result<u32> {
+        if let Some(dti) = DTI::from_str(dti) {
+            return Ok(dti.hash());
+        }
+
+        u32::from_str_radix(dti.trim_start_matches("0x"), 16)
+            .map_err(|_| anyhow!("invalid dti: {}", dti))
+    }
+
+    pub enum ArchiveEdit<'a> {
+        // Without a quality, the one of the last entry of the same type is
+        // used
+        Add {
+            path: &'a str,
+            quality: Option<u32>,
+            data: &'a [u8],
+        },
+        Replace {
+            path: &'a str,
+            data: &'a [u8],
+        },
+        Remove {
+            path: &'a str,
+        },
+        Rename {
+            path: &'a str,
+            new_path: &'a str,
+        },
+    }
+
+    /// Apply a single edit to an archive, rewriting it in place. The data for
+    /// every other resource is copied through unchanged.
+    pub fn edit_archive(
+        archive_path: &Path,
+        dti_hash: u32,
+        edit: ArchiveEdit,
+        format: Option<ArchiveFormat>,
+        key: Option<&ArchiveKey>,
+    ) -> anyhow::Result<()> {
+        let archive = ArchiveFile::open(std::fs::File::open(archive_path)?, format, key.cloned())?;
+        let mut archive_writer = ArchiveWriter::from_archive(&archive)?;
+
+        match edit {
+            ArchiveEdit::Add {
+                path,
+                quality,
+                data,
+            } => {
+                if archive_writer.contains_file(path, dti_hash) {
+                    return Err(anyhow!(
+                        "resource {:?} ({:08x}) already exists",
+                        path,
+                        dti_hash
+                    ));
+                }
+
+                let quality = quality.unwrap_or_else(|| {
+                    archive
+                        .resource_infos()
+                        .iter()
+                        .rev()
+                        .find(|info| info.dti_hash() == dti_hash)
+                        .map_or(0, ResourceInfo::quality)
+                });
+
+                archive_writer.add_file_with_hash(path, dti_hash, quality, data)?
+            }
+            ArchiveEdit::Replace { path, data } => {
+                archive_writer.replace_file(path, dti_hash, data)?
+            }
+            ArchiveEdit::Remove { path } => archive_writer.remove_file(path, dti_hash)?,
+            ArchiveEdit::Rename { path, new_path } => {
+                archive_writer.rename_file(path, dti_hash, new_path)?
+            }
+        }
+        drop(archive);
+
+        save_archive(&archive_writer, archive_path)
+    }
 }
 
 #[test]
@@ -364,5 +1968,529 @@ fn test_struct_sizes() {
     use std::mem::size_of;
 
     assert_eq!(size_of::<ArchiveHeader>(), 8);
-    assert_eq!(size_of::<RawResourceInfo>(), 0x90);
+    assert_eq!(ArchiveFormat::default().resource_info_size(), 0x90);
+}
+
+#[test]
+fn test_entry_reader_seek() {
+    let data: Vec<u8> = (0..4096u32).map(|v| (v % 251) as u8).collect();
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&data).unwrap();
+
+    let mut reader = ArchiveEntryReader::new(encoder.finish().unwrap(), data.len() as u64);
+
+    let mut buf = [0u8; 16];
+    reader.seek(SeekFrom::Start(1000)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&data[1000..1016], &buf);
+
+    reader.seek(SeekFrom::Current(-516)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&data[500..516], &buf);
+
+    assert_eq!(4080, reader.seek(SeekFrom::End(-16)).unwrap());
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&data[4080..], &buf);
+}
+
+#[test]
+fn test_duplicate_entries() {
+    let dti_hash = crate::DTIs::rTexture.hash();
+
+    let mut writer = ArchiveWriter::new();
+    for data in [b"first", b"other"] {
+        writer.push_resource(
+            "test\\a",
+            dti_hash,
+            0,
+            ResourceDataForWrite::Uncompressed(data.to_vec()),
+            None,
+        );
+    }
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+    assert_eq!(
+        b"first".as_slice(),
+        archive
+            .get_resource_by_hash("TEST\\A", dti_hash)
+            .unwrap()
+            .unwrap()
+    );
+
+    // Each entry can still be read through its own info
+    for (info, expected) in archive.resource_infos().iter().zip([b"first", b"other"]) {
+        assert_eq!(
+            expected.as_slice(),
+            archive.get_resource_by_info(info).unwrap().unwrap()
+        );
+
+        let mut data = vec![];
+        archive
+            .open_resource_by_info(info)
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(expected.as_slice(), data);
+    }
+}
+
+#[test]
+fn test_unknown_dti_round_trip() {
+    const UNKNOWN_DTI_HASH: u32 = 0x1234_5678;
+    assert!(DTI::from_hash(UNKNOWN_DTI_HASH).is_none());
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file_with_hash("test\\unknown", UNKNOWN_DTI_HASH, 0, b"unknown data")
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+    let info = &archive.resource_infos()[0];
+
+    assert!(info.dti().is_none());
+    assert_eq!(UNKNOWN_DTI_HASH, info.dti_hash());
+    assert_eq!("12345678", info.file_ext());
+    assert_eq!(
+        b"unknown data".as_slice(),
+        archive.get_resource_by_info(info).unwrap().unwrap()
+    );
+}
+
+#[test]
+fn test_original_layout_round_trip() {
+    let mut writer = ArchiveWriter::new();
+    writer.set_compression(Compression::best());
+    writer.set_original_size(0x9000);
+
+    writer
+        .add_file("test\\a", &crate::DTIs::rTexture, 0, &[1u8; 0x100])
+        .unwrap();
+    writer
+        .add_file("test\\b", &crate::DTIs::rModel, 1, &[2u8; 0x200])
+        .unwrap();
+    writer
+        .set_original_offset("test\\a", crate::DTIs::rTexture.hash(), 0x8100)
+        .unwrap();
+    writer
+        .set_original_offset("test\\b", crate::DTIs::rModel.hash(), 0x8000)
+        .unwrap();
+
+    let mut original_bytes = vec![];
+    writer.save(&mut original_bytes).unwrap();
+    assert_eq!(0x9000, original_bytes.len());
+
+    // Padding isn't always zeroes
+    original_bytes[0x7000..0x7010].fill(0xcd);
+    original_bytes[0x8ff0..].fill(0xab);
+
+    let original = ArchiveFile::new(Cursor::new(original_bytes.clone())).unwrap();
+
+    let mut writer = ArchiveWriter::new();
+    writer.set_original_size(0x9000);
+    writer.set_original_gaps(original.gaps().unwrap());
+
+    for info in original.resource_infos() {
+        writer
+            .add_compressed_file_with_hash(
+                info.path(),
+                info.dti_hash(),
+                info.quality(),
+                info.size_uncompressed(),
+                &original.get_resource_compressed(info).unwrap(),
+            )
+            .unwrap();
+        writer
+            .set_original_offset(info.path(), info.dti_hash(), info.offset())
+            .unwrap();
+    }
+
+    let mut repacked_bytes = vec![];
+    writer.save(&mut repacked_bytes).unwrap();
+
+    assert_eq!(original_bytes, repacked_bytes);
+}
+
+#[test]
+fn test_edit_archive() {
+    let texture_hash = crate::DTIs::rTexture.hash();
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("a", &crate::DTIs::rTexture, 0, b"a")
+        .unwrap();
+    writer
+        .add_file("b", &crate::DTIs::rTexture, 0, b"b")
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+
+    let mut writer = ArchiveWriter::from_archive(&archive).unwrap();
+    // Paths are looked up ignoring case
+    assert!(writer.contains_file("A", texture_hash));
+    writer.remove_file("A", texture_hash).unwrap();
+    writer.rename_file("b", texture_hash, "c").unwrap();
+    writer.rename_file("C", texture_hash, "c").unwrap();
+    writer.replace_file("C", texture_hash, b"replaced").unwrap();
+    assert!(writer.remove_file("b", texture_hash).is_err());
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+
+    assert_eq!(1, archive.resource_infos().len());
+    assert_eq!(
+        b"replaced".as_slice(),
+        archive
+            .get_resource("c", &crate::DTIs::rTexture)
+            .unwrap()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_repack_compression_levels() {
+    use cli_util::{repack_archive, unpack_archive};
+
+    let temp_dir = crate::util::TempDir::new("rarchive_levels").unwrap();
+    let archive_path = temp_dir.path().join("test.arc");
+    let unpacked_path = temp_dir.path().join("test");
+
+    let data: Vec<u8> = (0..0x1000u32).map(|v| (v % 7) as u8).collect();
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("a", &crate::DTIs::rTexture, 0, &data)
+        .unwrap();
+    writer
+        .add_file("b", &crate::DTIs::rModel, 0, &data[1..])
+        .unwrap();
+    writer
+        .set_file_compression("a", crate::DTIs::rTexture.hash(), Compression::fast())
+        .unwrap();
+    writer
+        .set_file_compression("b", crate::DTIs::rModel.hash(), Compression::best())
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    std::fs::write(&archive_path, archive_bytes).unwrap();
+
+    std::fs::create_dir(&unpacked_path).unwrap();
+    unpack_archive(&archive_path, &unpacked_path, None, None).unwrap();
+
+    // Both modified, so they get recompressed with their own levels
+    for (name, dti) in [("a", &crate::DTIs::rTexture), ("b", &crate::DTIs::rModel)] {
+        let file_path = unpacked_path
+            .join(name)
+            .with_extension(dti.file_ext().unwrap());
+        assert!(file_path.is_file());
+        std::fs::write(file_path, &data[2..]).unwrap();
+    }
+    repack_archive(&unpacked_path, &archive_path, true, None, None).unwrap();
+
+    let archive = ArchiveFile::new(std::fs::File::open(&archive_path).unwrap()).unwrap();
+    let flevels: Vec<u8> = archive
+        .resource_infos()
+        .iter()
+        .map(|info| archive.get_resource_compressed(info).unwrap()[1] >> 6)
+        .collect();
+    assert_eq!(vec![0, 3], flevels);
+}
+
+#[test]
+fn test_data_offsets_overflow() {
+    let data = [Cow::Borrowed(&[0u8; 0x10][..])];
+
+    assert_eq!(
+        vec![u32::MAX - 0x10],
+        ArchiveWriter::data_offsets(8, &[Some(u32::MAX - 0x10)], &data).unwrap()
+    );
+    assert!(ArchiveWriter::data_offsets(8, &[Some(u32::MAX - 0xf)], &data).is_err());
+}
+
+#[test]
+fn test_verify_archive() {
+    let mut writer = ArchiveWriter::new();
+    for (idx, path) in ["a", "b", "c", "d", "e", "f"].iter().enumerate() {
+        writer
+            .add_file(path, &crate::DTIs::rTexture, 0, &[idx as u8; 0x100])
+            .unwrap();
+    }
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes.clone())).unwrap();
+    assert!(archive.verify().unwrap().iter().all(EntryReport::is_ok));
+    let a_offset = archive.resource_infos()[0].offset();
+    let archive_size = archive_bytes.len() as u32;
+
+    let format = ArchiveFormat::default();
+    let info_start = |idx: usize| size_of::<ArchiveHeader>() + idx * format.resource_info_size();
+    let mut set_field = |idx: usize, field: usize, value: u32| {
+        let start = info_start(idx) + format.path_size + field * 4;
+        archive_bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+    };
+
+    // b is short and inside of a, c starts after b ends but is still inside a
+    set_field(1, 1, 1);
+    set_field(1, 3, a_offset + 2);
+    set_field(2, 3, a_offset + 4);
+    set_field(3, 3, 0);
+    set_field(4, 3, archive_size);
+    set_field(5, 2, 0x101);
+    archive_bytes[info_start(5)..info_start(5) + format.path_size].fill(b'f');
+
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+    let reports = archive.verify().unwrap();
+
+    assert!(reports[0].is_ok());
+    assert!(matches!(
+        reports[1].problems(),
+        [EntryProblem::Overlaps { path, .. }, EntryProblem::Decompression(_)] if path == "a"
+    ));
+    assert!(matches!(
+        reports[2].problems().first(),
+        Some(EntryProblem::Overlaps { path, .. }) if path == "a"
+    ));
+    assert!(matches!(
+        reports[3].problems().first(),
+        Some(EntryProblem::OverlapsHeader)
+    ));
+    assert!(matches!(
+        reports[4].problems(),
+        [EntryProblem::OutOfBounds { archive_size: size }] if *size == archive_size as u64
+    ));
+    assert!(matches!(
+        reports[5].problems(),
+        [
+            EntryProblem::PathNotTerminated,
+            EntryProblem::SizeMismatch {
+                expected: 0x101,
+                actual: 0x100
+            }
+        ]
+    ));
+}
+
+#[test]
+fn test_format_detection() {
+    // Every known layout, and the same layouts byte swapped
+    let formats = KNOWN_FORMATS.iter().flat_map(|format| {
+        [Endianness::Little, Endianness::Big]
+            .map(|endianness| ArchiveFormat::new(format.version, endianness, format.path_size))
+    });
+
+    for format in formats {
+        let mut writer = ArchiveWriter::new();
+        writer.set_format(format);
+        writer
+            .add_file("test\\a", &crate::DTIs::rTexture, 2, b"data")
+            .unwrap();
+
+        let mut archive_bytes = vec![];
+        writer.save(&mut archive_bytes).unwrap();
+
+        let expected_magic: &[u8] = match format.endianness {
+            Endianness::Little => b"ARC\0",
+            Endianness::Big => b"\0CRA",
+        };
+        assert_eq!(expected_magic, &archive_bytes[..4]);
+
+        let archive = if KNOWN_FORMATS.contains(&format) {
+            ArchiveFile::new(Cursor::new(archive_bytes)).unwrap()
+        } else {
+            // The endianness is still detected from the magic
+            let err = ArchiveFile::new(Cursor::new(archive_bytes.clone()))
+                .err()
+                .unwrap();
+            assert!(err.to_string().contains("Big"), "{}", err);
+
+            ArchiveFile::with_format(Cursor::new(archive_bytes), format).unwrap()
+        };
+        assert_eq!(format, archive.format());
+
+        let info = &archive.resource_infos()[0];
+        assert_eq!("test\\a", info.path());
+        assert_eq!(2, info.quality());
+        assert_eq!(
+            b"data".as_slice(),
+            archive.get_resource_by_info(info).unwrap().unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_explicit_format() {
+    let format = ArchiveFormat::new(8, Endianness::Big, 64);
+    assert!(!KNOWN_FORMATS.contains(&format));
+
+    let mut writer = ArchiveWriter::new();
+    writer.set_format(format);
+    writer
+        .add_file("test\\a", &crate::DTIs::rTexture, 2, b"data")
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    assert_eq!(b"\0CRA", &archive_bytes[..4]);
+
+    // Not detected, the format has to be given
+    assert!(ArchiveFile::new(Cursor::new(archive_bytes.clone())).is_err());
+
+    let archive = ArchiveFile::with_format(Cursor::new(archive_bytes), format).unwrap();
+    let info = &archive.resource_infos()[0];
+    assert_eq!("test\\a", info.path());
+    assert_eq!(2, info.quality());
+    assert_eq!(
+        b"data".as_slice(),
+        archive.get_resource_by_info(info).unwrap().unwrap()
+    );
+}
+
+#[test]
+fn test_parse_format() {
+    assert_eq!(
+        ArchiveFormat::new(7, Endianness::Little, 128),
+        "7:le:128".parse().unwrap()
+    );
+    assert_eq!(
+        ArchiveFormat::new(8, Endianness::Big, 64),
+        "8:BE:64".parse().unwrap()
+    );
+
+    for invalid in [
+        "",
+        "7",
+        "7:le",
+        "7:xe:128",
+        "7:le:0",
+        "x:le:128",
+        "7:le:128:1",
+    ] {
+        assert!(invalid.parse::<ArchiveFormat>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_encrypted_round_trip() {
+    let key = ArchiveKey::new(b"test key").unwrap();
+
+    let mut writer = ArchiveWriter::new();
+    writer.set_key(Some(key.clone()));
+    writer
+        .add_file("test\\a", &crate::DTIs::rTexture, 0, &[3u8; 0x123])
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+    assert_eq!(b"ARCC", &archive_bytes[..4]);
+
+    assert!(ArchiveFile::new(Cursor::new(archive_bytes.clone())).is_err());
+
+    let archive = ArchiveFile::with_key(Cursor::new(archive_bytes), key).unwrap();
+    assert!(archive.is_encrypted());
+    assert_eq!(
+        [3u8; 0x123].as_slice(),
+        archive
+            .get_resource("test\\a", &crate::DTIs::rTexture)
+            .unwrap()
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_shared_data() {
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("a", &crate::DTIs::rTexture, 0, b"shared data")
+        .unwrap();
+    writer
+        .add_file("b", &crate::DTIs::rTexture, 0, b"other data")
+        .unwrap();
+    writer
+        .add_file("c", &crate::DTIs::rTexture, 0, b"shared data")
+        .unwrap();
+
+    let mut archive_bytes = vec![];
+    writer.save(&mut archive_bytes).unwrap();
+
+    let archive = ArchiveFile::new(Cursor::new(archive_bytes)).unwrap();
+    let infos = archive.resource_infos();
+
+    assert_eq!(infos[0].offset(), infos[2].offset());
+    assert_ne!(infos[0].offset(), infos[1].offset());
+    assert_eq!(
+        b"shared data".as_slice(),
+        archive
+            .get_resource(infos[2].path(), &crate::DTIs::rTexture)
+            .unwrap()
+            .unwrap()
+    );
+    assert!(archive.verify().unwrap().iter().all(EntryReport::is_ok));
+
+    // Repacking keeps the data shared
+    let mut repacked_bytes = vec![];
+    ArchiveWriter::from_archive(&archive)
+        .unwrap()
+        .save(&mut repacked_bytes)
+        .unwrap();
+
+    let repacked = ArchiveFile::new(Cursor::new(repacked_bytes)).unwrap();
+    let repacked_infos = repacked.resource_infos();
+    assert_eq!(repacked_infos[0].offset(), repacked_infos[2].offset());
+}
+
+#[test]
+fn test_mapped_archive() {
+    let mut writer = ArchiveWriter::new();
+    for idx in 0..16 {
+        writer
+            .add_file(
+                &format!("test\\{}", idx),
+                &crate::DTIs::rTexture,
+                0,
+                format!("data {}", idx).as_bytes(),
+            )
+            .unwrap();
+    }
+
+    let temp_dir = util::TempDir::new("mapped_archive").unwrap();
+    let path = temp_dir.path().join("test.arc");
+    let mut file = File::create(&path).unwrap();
+    writer.save(&mut file).unwrap();
+    drop(file);
+
+    // SAFETY: nothing else uses the file
+    let archive =
+        unsafe { ArchiveFile::open_mapped(&File::open(&path).unwrap(), None, None) }.unwrap();
+
+    let datas: Vec<Vec<u8>> = archive
+        .resource_infos()
+        .par_iter()
+        .map(|info| archive.get_resource_by_info(info).unwrap().unwrap())
+        .collect();
+
+    for (idx, data) in datas.iter().enumerate() {
+        assert_eq!(format!("data {}", idx).as_bytes(), data);
+    }
+
+    assert!(matches!(
+        archive.get_resource_compressed(&archive.resource_infos()[0]),
+        Ok(Cow::Borrowed(_))
+    ));
+    assert!(archive.verify().unwrap().iter().all(EntryReport::is_ok));
+
+    // Unmap before temp_dir removes the file, which fails on Windows otherwise
+    drop(archive);
 }
@@ -0,0 +1,333 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    path::PathBuf,
+};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    mtserializer::{self, Class, PropertyValue},
+    resource_manager::ResourceManager,
+    resource_path::ResourcePath,
+    rmaterial::MaterialFile,
+    rscheduler::SchedulerFile,
+    DTIs, DTI,
+};
+
+#[derive(Debug, Serialize)]
+pub struct DependencyNode {
+    pub path: String,
+    // The DTI name, or the hash in hex if it isn't known
+    pub dti: String,
+    pub dti_hash: u32,
+    // Mount point the resource was found in, None if it's dangling
+    pub mount: Option<PathBuf>,
+    // Why the resource's references couldn't be read, if they couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DependencyNode {
+    /// Referenced, but not found by the ResourceManager
+    pub fn is_dangling(&self) -> bool {
+        self.mount.is_none()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyEdge {
+    // Indices into DependencyGraph::nodes
+    pub from: usize,
+    pub to: usize,
+    // What the reference is, e.g. the property name
+    pub label: String,
+}
+
+/// Every resource that a resource pulls in, directly or through other
+/// resources. The first node is the resource the graph was built from.
+#[derive(Debug, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<DependencyNode>,
+    pub edges: Vec<DependencyEdge>,
+}
+
+// A reference to another resource, as (label, path, DTI hash)
+type Reference = (String, ResourcePath, u32);
+
+/// Resource customs (like mpModel) are (type name, path)
+fn class_references(class: &Class, references: &mut Vec<Reference>) {
+    for (name, prop) in class.props() {
+        for value in prop.values() {
+            match value {
+                PropertyValue::Custom(custom) => {
+                    let [type_name, path] = custom.as_slice() else {
+                        continue;
+                    };
+                    if path.is_empty() {
+                        continue;
+                    }
+
+                    match DTI::from_str(type_name) {
+                        Some(dti) => references.push((name.clone(), path.into(), dti.hash())),
+                        None => debug!("ignoring custom {} of unknown type {:?}", name, type_name),
+                    }
+                }
+                PropertyValue::Class(Some(class)) => class_references(class, references),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn resource_references(
+    resource_manager: &ResourceManager,
+    path: &ResourcePath,
+    dti: &DTI,
+) -> anyhow::Result<Vec<Reference>> {
+    let mut references = vec![];
+
+    if dti.is_type_of(&DTIs::nGO__rCharacter) {
+        // Loaders are only registered for rCharacter itself, not for the
+        // types derived from it
+        let mut resource = resource_manager.get_resource(path, dti)?;
+        let character = mtserializer::deserialize(&mut resource)?;
+        class_references(&character, &mut references);
+    } else if *dti == DTIs::rModel {
+        // Models don't name their material, it's always next to them
+        references.push(("material".to_string(), path.clone(), DTIs::rMaterial.hash()));
+    } else if *dti == DTIs::rMaterial {
+        let mut resource = resource_manager.get_resource(path, dti)?;
+        for (dti_hash, texture) in MaterialFile::read_textures(&mut resource)? {
+            // Unused slot
+            if dti_hash == 0 {
+                continue;
+            }
+
+            references.push(("texture".to_string(), texture.as_str().into(), dti_hash));
+        }
+    } else if *dti == DTIs::rScheduler {
+        let scheduler = resource_manager.load::<SchedulerFile>(path)?;
+        for (dti_hash, resource_path) in scheduler.resources() {
+            references.push(("key".to_string(), resource_path.into(), *dti_hash));
+        }
+    }
+
+    Ok(references)
+}
+
+impl DependencyGraph {
+    /// Follow the references of a resource transitively. References that
+    /// can't be found are added as dangling nodes, and not followed further.
+    /// Resources that fail to parse get an error, the rest of the graph is
+    /// still built.
+    pub fn build(
+        resource_manager: &ResourceManager,
+        path: impl Into<ResourcePath>,
+        dti: &DTI,
+    ) -> Self {
+        let mut graph = Self {
+            nodes: vec![],
+            edges: vec![],
+        };
+        // (path, DTI hash) -> index into nodes
+        let mut node_indices = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        let mut add_node = |graph: &mut Self, path: ResourcePath, dti_hash: u32| {
+            if let Some(idx) = node_indices.get(&(path.clone(), dti_hash)) {
+                return (*idx, None);
+            }
+
+            // Types that aren't in the DTI table can still be found, but
+            // their references can't be read
+            let dti = DTI::from_hash(dti_hash);
+            let mount = resource_manager
+                .resource_layer_by_hash(&path, dti_hash)
+                .map(|mount| mount.path().to_path_buf());
+
+            let idx = graph.nodes.len();
+            graph.nodes.push(DependencyNode {
+                path: path.to_string(),
+                dti: dti
+                    .map(|dti| dti.name().to_string())
+                    .unwrap_or_else(|| format!("{:08x}", dti_hash)),
+                dti_hash,
+                mount: mount.clone(),
+                error: None,
+            });
+            node_indices.insert((path.clone(), dti_hash), idx);
+
+            // Only resources that exist are followed
+            let dti = dti.filter(|_| mount.is_some());
+            (idx, dti.map(|dti| (path, dti)))
+        };
+
+        let (_, root) = add_node(&mut graph, path.into(), dti.hash());
+        queue.extend(root.map(|(path, dti)| (0, path, dti)));
+
+        while let Some((from, path, dti)) = queue.pop_front() {
+            let references = match resource_references(resource_manager, &path, dti) {
+                Ok(references) => references,
+                Err(err) => {
+                    warn!("can't read references of {:?}: {:#}", path, err);
+                    graph.nodes[from].error = Some(format!("{:#}", err));
+                    continue;
+                }
+            };
+
+            for (label, ref_path, ref_dti_hash) in references {
+                let (to, new_node) = add_node(&mut graph, ref_path, ref_dti_hash);
+                graph.edges.push(DependencyEdge { from, to, label });
+
+                queue.extend(new_node.map(|(path, dti)| (to, path, dti)));
+            }
+        }
+
+        graph
+    }
+
+    /// Nodes that are referenced, but couldn't be found
+    pub fn dangling(&self) -> impl Iterator<Item = &DependencyNode> {
+        self.nodes.iter().filter(|node| node.is_dangling())
+    }
+
+    /// Nodes whose references couldn't be read
+    pub fn errors(&self) -> impl Iterator<Item = &DependencyNode> {
+        self.nodes.iter().filter(|node| node.error.is_some())
+    }
+
+    /// Graphviz representation, with dangling references in red and resources
+    /// that failed to parse in orange
+    pub fn to_dot(&self) -> String {
+        // Paths are '\\' separated
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut dot = String::from("digraph dependencies {\n");
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let style = if node.is_dangling() {
+                ", color=red, style=dashed"
+            } else if node.error.is_some() {
+                ", color=orange"
+            } else {
+                ""
+            };
+
+            writeln!(
+                dot,
+                "    {} [label=\"{}\\n({})\"{}];",
+                idx,
+                escape(&node.path),
+                escape(&node.dti),
+                style
+            )
+            .unwrap();
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "    {} -> {} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                escape(&edge.label)
+            )
+            .unwrap();
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+#[test]
+fn test_dependency_graph() {
+    use std::{fs::File, mem::size_of, path::Path};
+
+    use crate::rarchive::ArchiveWriter;
+
+    let temp_dir = crate::util::TempDir::new("resource_deps").unwrap();
+    let root = temp_dir.path();
+
+    // Not in the DTI table, but still found by its hash
+    const UNKNOWN_DTI_HASH: u32 = 0x1234_5678;
+
+    // Material header followed by the texture infos (dti hash, padding, two
+    // pointers, 128 byte path)
+    let textures = [
+        (DTIs::rTexture.hash(), "tex\\body"),
+        (DTIs::rTexture.hash(), "tex\\missing"),
+        (0, ""),
+        (DTIs::rMaterial.hash(), "tex\\broken"),
+        (UNKNOWN_DTI_HASH, "tex\\unknown"),
+    ];
+    let header_size = 0x28;
+    let mut material = vec![0u8; header_size];
+    material[0xc..0x10].copy_from_slice(&(textures.len() as u32).to_le_bytes());
+    material[0x18..0x20].copy_from_slice(&(header_size as u64).to_le_bytes());
+    for (dti_hash, texture_path) in textures {
+        let mut texture_info = vec![0u8; 0x18 + 128];
+        texture_info[..size_of::<u32>()].copy_from_slice(&dti_hash.to_le_bytes());
+        texture_info[0x18..0x18 + texture_path.len()].copy_from_slice(texture_path.as_bytes());
+        material.extend(texture_info);
+    }
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("model\\pl00", &DTIs::rModel, 0, b"model")
+        .unwrap();
+    writer
+        .add_file("model\\pl00", &DTIs::rMaterial, 0, &material)
+        .unwrap();
+    writer
+        .add_file("tex\\body", &DTIs::rTexture, 0, b"tex")
+        .unwrap();
+    writer
+        .add_file("tex\\broken", &DTIs::rMaterial, 0, b"bad")
+        .unwrap();
+    writer
+        .add_file_with_hash("tex\\unknown", UNKNOWN_DTI_HASH, 0, b"unknown")
+        .unwrap();
+    writer
+        .save(&mut File::create(root.join("deps.arc")).unwrap())
+        .unwrap();
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.add_archive(Path::new("deps")).unwrap();
+
+    let graph = DependencyGraph::build(&resource_manager, "model\\pl00", &DTIs::rModel);
+
+    let nodes: Vec<_> = graph
+        .nodes
+        .iter()
+        .map(|node| (node.path.as_str(), node.dti.as_str(), node.is_dangling()))
+        .collect();
+    assert_eq!(
+        vec![
+            ("model\\pl00", "rModel", false),
+            ("model\\pl00", "rMaterial", false),
+            ("tex\\body", "rTexture", false),
+            ("tex\\missing", "rTexture", true),
+            ("tex\\broken", "rMaterial", false),
+            ("tex\\unknown", "12345678", false),
+        ],
+        nodes
+    );
+
+    let edges: Vec<_> = graph
+        .edges
+        .iter()
+        .map(|edge| (edge.from, edge.to))
+        .collect();
+    assert_eq!(vec![(0, 1), (1, 2), (1, 3), (1, 4), (1, 5)], edges);
+
+    assert_eq!(1, graph.dangling().count());
+    let errors: Vec<_> = graph.errors().map(|node| node.path.as_str()).collect();
+    assert_eq!(vec!["tex\\broken"], errors);
+    assert!(graph
+        .to_dot()
+        .contains(r#"3 [label="tex\\missing\n(rTexture)", color=red, style=dashed];"#));
+}
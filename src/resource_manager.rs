@@ -1,114 +1,951 @@
 use std::{
-    collections::HashMap,
+    any::Any,
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
+    io::{Read, Seek},
     path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
 };
 
-use crate::{rarchive::ArchiveFile, DTIs, DTI};
-use anyhow::anyhow;
-use log::trace;
+use crate::{
+    archive_index::ArchiveIndex,
+    mtserializer,
+    rarchive::{file_ext_for_dti_hash, ArchiveFile, ArchiveFormat, ArchiveKey},
+    resource_path::ResourcePath,
+    resource_source::{DirectorySource, LooseFileTypes, ResourceSource, ZipSource},
+    rguimessage::GuiMessageFile,
+    rmaterial::MaterialFile,
+    rmodel::ModelFile,
+    rscheduler::SchedulerFile,
+    rshader2::Shader2File,
+    rshaderpackage::ShaderPackageFile,
+    rtexture::TextureFile,
+    DTIs, DTI,
+};
+use anyhow::{anyhow, Context};
+use globset::GlobBuilder;
+use log::{debug, trace, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 
-enum ResourceInner {
-    FileBacked(BufReader<File>),
-    ArchiveBacked(Cursor<Vec<u8>>),
-}
+// Box<dyn Read + Seek> isn't allowed
+trait ResourceReader: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ResourceReader for T {}
 
-pub struct Resource(ResourceInner);
+pub struct Resource(Box<dyn ResourceReader>);
+
+impl Resource {
+    pub fn new(reader: impl Read + Seek + Send + 'static) -> Self {
+        Self(Box::new(reader))
+    }
+}
 
 impl Read for Resource {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match &mut self.0 {
-            ResourceInner::FileBacked(ref mut r) => r.read(buf),
-            ResourceInner::ArchiveBacked(ref mut r) => r.read(buf),
-        }
+        self.0.read(buf)
     }
 }
 
 impl Seek for Resource {
     fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        match &mut self.0 {
-            ResourceInner::FileBacked(ref mut r) => r.seek(pos),
-            ResourceInner::ArchiveBacked(ref mut r) => r.seek(pos),
+        self.0.seek(pos)
+    }
+}
+
+/// A resource that can be loaded from a ResourceManager
+#[derive(Debug, Clone)]
+pub struct ResourceEntry {
+    path: ResourcePath,
+    dti_hash: u32,
+    dti: Option<&'static DTI>,
+    size_uncompressed: u64,
+    // Only known for resources in archives
+    size_compressed: Option<u32>,
+    quality: Option<u32>,
+    mount_path: PathBuf,
+}
+
+impl ResourceEntry {
+    /// For ResourceSource::resources
+    pub fn new(path: ResourcePath, dti_hash: u32, size_uncompressed: u64) -> Self {
+        Self {
+            path,
+            dti_hash,
+            dti: DTI::from_hash(dti_hash),
+            size_uncompressed,
+            size_compressed: None,
+            quality: None,
+            mount_path: PathBuf::new(),
         }
     }
+
+    /// For resources in .arc files
+    pub fn with_archive_info(mut self, size_compressed: u32, quality: u32) -> Self {
+        self.size_compressed = Some(size_compressed);
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn path(&self) -> &ResourcePath {
+        &self.path
+    }
+
+    /// '/' separated path, including the file extension for the DTI
+    pub fn path_with_ext(&self) -> PathBuf {
+        let file_ext = match self.dti.and_then(|dti| dti.file_ext()) {
+            Some(file_ext) => file_ext.to_string(),
+            None => file_ext_for_dti_hash(self.dti_hash),
+        };
+
+        self.path.to_path_buf().with_extension(file_ext)
+    }
+
+    pub fn dti_hash(&self) -> u32 {
+        self.dti_hash
+    }
+
+    pub fn dti(&self) -> Option<&'static DTI> {
+        self.dti
+    }
+
+    pub fn size_uncompressed(&self) -> u64 {
+        self.size_uncompressed
+    }
+
+    pub fn size_compressed(&self) -> Option<u32> {
+        self.size_compressed
+    }
+
+    pub fn quality(&self) -> Option<u32> {
+        self.quality
+    }
+
+    /// Path of the mount point the resource is loaded from
+    pub fn mount_path(&self) -> &Path {
+        &self.mount_path
+    }
+}
+
+/// A loose file that was created, modified or removed while it was watched
+#[derive(Debug, Clone)]
+pub struct ResourceChange {
+    pub path: ResourcePath,
+    pub dti: &'static DTI,
+    // The directory mount point it's in
+    pub mount_path: PathBuf,
 }
 
+/// One layer of the ResourceManager's file system
+pub struct MountPoint {
+    path: PathBuf,
+    priority: i32,
+    // Shared with the file watcher, which clears its cache when files change
+    source: Arc<dyn ResourceSource>,
+
+    // Only for loose files, once ResourceManager::watch_loose_files is called
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl MountPoint {
+    fn new(path: &Path, priority: i32, source: Arc<dyn ResourceSource>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            priority,
+            source,
+            watcher: None,
+        }
+    }
+
+    /// The directory or file that was mounted, or the path given to
+    /// ResourceManager::mount
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn source(&self) -> &dyn ResourceSource {
+        self.source.as_ref()
+    }
+
+    /// The source, if it's a T
+    pub fn source_as<T: ResourceSource>(&self) -> Option<&T> {
+        (self.source.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub fn is_archive(&self) -> bool {
+        self.source_as::<ArchiveFile<File>>().is_some()
+    }
+
+    /// For an archive index, the archive that a resource would be loaded from
+    pub fn indexed_archive_path(&self, path: &ResourcePath, dti: &DTI) -> Option<PathBuf> {
+        self.source_as::<ArchiveIndex>()?
+            .archive_path(path, dti.hash())
+    }
+
+    /// Every resource in this mount point, including ones that are hidden by
+    /// higher priority mount points
+    pub fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        let mut resources = self.source.resources()?;
+        for resource in &mut resources {
+            resource.mount_path = self.path.clone();
+        }
+
+        Ok(resources)
+    }
+
+    // Forget the source's cache and loaded copies of files that change, then
+    // send the change
+    fn watch(
+        &mut self,
+        cache: ResourceCache,
+        sender: Sender<ResourceChange>,
+    ) -> anyhow::Result<()> {
+        let Some(loose_files_path) = self.source.loose_files_path() else {
+            return Err(anyhow!("{:?} doesn't have loose files", self.path));
+        };
+
+        // Event paths are under the watched path, which is canonicalized so
+        // relative mount paths work too
+        let root = loose_files_path.canonicalize()?;
+        let mount_path = self.path.clone();
+        let source = self.source.clone();
+        let loose_file_types = LooseFileTypes::new();
+
+        let mut watcher = notify::recommended_watcher({
+            let root = root.clone();
+
+            move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        warn!("error watching {:?}: {}", mount_path, err);
+                        return;
+                    }
+                };
+
+                if matches!(event.kind, EventKind::Access(_)) {
+                    return;
+                }
+
+                // Files might have been created, removed or renamed
+                source.clear_cache();
+
+                for fs_path in &event.paths {
+                    let Ok(relative_path) = fs_path.strip_prefix(&root) else {
+                        continue;
+                    };
+
+                    for (path, dti) in loose_file_types.resources(relative_path) {
+                        debug!("resource {:?} ({}) changed", path, dti.name());
+
+                        cache.lock().unwrap().remove(&(path.clone(), dti.hash()));
+                        // Nobody might be listening anymore, which is fine
+                        let _ = sender.send(ResourceChange {
+                            path,
+                            dti,
+                            mount_path: mount_path.clone(),
+                        });
+                    }
+                }
+            }
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        self.watcher = Some(watcher);
+
+        Ok(())
+    }
+}
+
+/// A parsed resource, that can be loaded with ResourceManager::load
+pub trait TypedResource: Any + Send + Sync {
+    fn dti() -> &'static DTI;
+}
+
+impl TypedResource for TextureFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rTexture
+    }
+}
+
+impl TypedResource for ModelFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rModel
+    }
+}
+
+impl TypedResource for MaterialFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rMaterial
+    }
+}
+
+impl TypedResource for Shader2File {
+    fn dti() -> &'static DTI {
+        &DTIs::rShader2
+    }
+}
+
+impl TypedResource for ShaderPackageFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rShaderPackage
+    }
+}
+
+impl TypedResource for GuiMessageFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rGUIMessage
+    }
+}
+
+impl TypedResource for SchedulerFile {
+    fn dti() -> &'static DTI {
+        &DTIs::rScheduler
+    }
+}
+
+type LoadedResource = Arc<dyn Any + Send + Sync>;
+// (path, DTI hash) -> loaded resource
+type ResourceCache = Arc<Mutex<HashMap<(ResourcePath, u32), LoadedResource>>>;
+type Loader =
+    Box<dyn Fn(&mut Resource, &ResourceManager) -> anyhow::Result<LoadedResource> + Send + Sync>;
+
+/// Resources are looked up in an ordered stack of mount points. Higher
+/// priorities win, and for the same priority, whatever was mounted last wins.
 pub struct ResourceManager {
     base_path: PathBuf,
-    loaded_archives: HashMap<PathBuf, ArchiveFile<File>>,
+    mounts: Vec<MountPoint>,
+    // Used for encrypted archives
+    archive_key: Option<ArchiveKey>,
+    // Used for archives with a layout that isn't detected
+    archive_format: Option<ArchiveFormat>,
+
+    // DTI hash -> loader
+    loaders: HashMap<u32, Loader>,
+    cache: ResourceCache,
+    // Set by watch_loose_files, for directories that are mounted after it
+    change_sender: Option<Sender<ResourceChange>>,
 }
 
 impl ResourceManager {
+    /// Priority of the base path, so that loose files override archives
+    pub const DIRECTORY_PRIORITY: i32 = 100;
+    /// Priority of archives added with add_archive
+    pub const ARCHIVE_PRIORITY: i32 = 0;
+    /// Priority of the archive index, so that archives which were added
+    /// explicitly still win
+    pub const INDEX_PRIORITY: i32 = -100;
+
     pub fn new(base_path: &Path) -> Self {
-        Self {
+        let mut resource_manager = Self {
             base_path: base_path.to_path_buf(),
-            loaded_archives: HashMap::new(),
+            mounts: vec![],
+            archive_key: None,
+            archive_format: None,
+            loaders: HashMap::new(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            change_sender: None,
+        };
+        resource_manager.mount_directory(base_path, Self::DIRECTORY_PRIORITY);
+
+        // Formats that need other resources to parse, like rMaterial (which
+        // needs an rShader2), have to be registered by the user
+        resource_manager.register_loader(&DTIs::rTexture, |reader, _| TextureFile::new(reader));
+        resource_manager.register_loader(&DTIs::rModel, |reader, _| ModelFile::new(reader));
+        resource_manager.register_loader(&DTIs::rShader2, |reader, _| Shader2File::new(reader));
+        resource_manager
+            .register_loader(&DTIs::rGUIMessage, |reader, _| GuiMessageFile::new(reader));
+        resource_manager.register_loader(&DTIs::rScheduler, |reader, _| SchedulerFile::new(reader));
+        resource_manager.register_loader(&DTIs::nGO__rCharacter, |reader, _| {
+            mtserializer::deserialize(reader)
+        });
+
+        resource_manager
+    }
+
+    /// Key for any encrypted archives that are added after this
+    pub fn set_archive_key(&mut self, key: ArchiveKey) {
+        self.archive_key = Some(key);
+    }
+
+    /// Format for any archives that are added after this, instead of
+    /// detecting it
+    pub fn set_archive_format(&mut self, format: ArchiveFormat) {
+        self.archive_format = Some(format);
+    }
+
+    /// All mount points, from highest to lowest priority
+    pub fn mounts(&self) -> &[MountPoint] {
+        &self.mounts
+    }
+
+    fn insert_mount(&mut self, mount: MountPoint) {
+        debug!("mounting {:?} with priority {}", mount.path, mount.priority);
+
+        let idx = self
+            .mounts
+            .iter()
+            .position(|other| other.priority <= mount.priority)
+            .unwrap_or(self.mounts.len());
+
+        // Loaded resources that the new mount point has might be overridden
+        // by it now
+        self.cache.lock().unwrap().retain(|(path, dti_hash), _| {
+            DTI::from_hash(*dti_hash).is_none_or(|dti| !mount.source.contains(path, dti))
+        });
+
+        self.mounts.insert(idx, mount);
+    }
+
+    /// Mount any source of resources. The path is only used to tell mount
+    /// points apart, and doesn't have to exist.
+    pub fn mount(&mut self, path: &Path, priority: i32, source: impl ResourceSource) {
+        let mut mount = MountPoint::new(path, priority, Arc::new(source));
+
+        if let Some(sender) = &self.change_sender {
+            if mount.source.loose_files_path().is_some() {
+                if let Err(err) = mount.watch(self.cache.clone(), sender.clone()) {
+                    warn!("couldn't watch {:?} for changes: {}", path, err);
+                }
+            }
         }
+
+        self.insert_mount(mount);
     }
 
-    pub fn add_archive(&mut self, path: &Path) -> anyhow::Result<()> {
-        if self.loaded_archives.contains_key(path) {
+    /// Mount a directory of loose files, like a mod folder or another game
+    /// root (e.g. DLC). Archives can also be added from it with add_archive.
+    pub fn mount_directory(&mut self, path: &Path, priority: i32) {
+        self.mount(path, priority, DirectorySource::new(path));
+    }
+
+    /// Mount a zip file of loose files, laid out like a mounted directory
+    pub fn mount_zip(&mut self, fs_path: &Path, priority: i32) -> anyhow::Result<()> {
+        self.mount(fs_path, priority, ZipSource::open(fs_path)?);
+
+        Ok(())
+    }
+
+    /// Watch every loose file mount point, including ones mounted later, for
+    /// changes. A changed resource is dropped from the cache before it's
+    /// sent, so loading it again gets the new version. Saving a file can
+    /// change it several times, so the same resource can be sent repeatedly.
+    pub fn watch_loose_files(&mut self) -> anyhow::Result<Receiver<ResourceChange>> {
+        let (sender, receiver) = mpsc::channel();
+
+        for mount in &mut self.mounts {
+            if mount.source.loose_files_path().is_some() {
+                mount.watch(self.cache.clone(), sender.clone())?;
+            }
+        }
+        self.change_sender = Some(sender);
+
+        Ok(receiver)
+    }
+
+    /// Mount the archive at fs_path. Does nothing if it's already mounted.
+    pub fn mount_archive(&mut self, fs_path: &Path, priority: i32) -> anyhow::Result<()> {
+        if self
+            .mounts
+            .iter()
+            .any(|mount| mount.is_archive() && mount.path == fs_path)
+        {
             return Ok(());
         }
 
-        let file = std::fs::File::open(
-            self.base_path
-                .join(path.with_extension(DTIs::rArchive.file_ext().unwrap())),
-        )?;
+        // Mounted archives stay open, so they aren't mapped
+        let file = File::open(fs_path)?;
+        let archive = ArchiveFile::open(file, self.archive_format, self.archive_key.clone())?;
 
-        let archive = ArchiveFile::new(file)?;
+        self.mount(fs_path, priority, archive);
 
-        self.loaded_archives.insert(path.to_path_buf(), archive);
+        Ok(())
+    }
+
+    /// Mount an archive by its resource path (without extension), from the
+    /// highest priority directory that has it
+    pub fn add_archive(&mut self, path: &Path) -> anyhow::Result<()> {
+        let archive_path = path.with_extension(DTIs::rArchive.file_ext().unwrap());
+
+        let fs_path = self
+            .mounts
+            .iter()
+            .filter_map(|mount| mount.source.loose_files_path())
+            .map(|path| path.join(&archive_path))
+            .find(|fs_path| fs_path.is_file())
+            .unwrap_or_else(|| self.base_path.join(&archive_path));
+
+        self.mount_archive(&fs_path, Self::ARCHIVE_PRIORITY)
+    }
+
+    /// Index every archive under the base path, so that resources can be
+    /// loaded without adding their archive first. The index is kept in
+    /// index_path, and is rebuilt for archives that changed since.
+    pub fn index_archives(&mut self, index_path: &Path) -> anyhow::Result<()> {
+        let index = ArchiveIndex::build(
+            &self.base_path,
+            index_path,
+            self.archive_format,
+            self.archive_key.clone(),
+        )?;
+
+        self.mounts
+            .retain(|mount| mount.source_as::<ArchiveIndex>().is_none());
+        self.mount(&self.base_path.clone(), Self::INDEX_PRIORITY, index);
 
         Ok(())
     }
 
+    /// If the path is formatted as "<archive>:<path>", mount that archive and
+    /// return just the resource path. Other paths are returned as is.
+    pub fn add_archive_from_path<'a>(&mut self, path: &'a str) -> anyhow::Result<&'a str> {
+        match path.split_once(':') {
+            Some((archive_path, path)) => {
+                self.add_archive(Path::new(archive_path))?;
+                Ok(path)
+            }
+            None => Ok(path),
+        }
+    }
+
     // Terrible name. If the path is formatted as "<archive>:<path>", then load
     // the resource from that archive
     pub fn get_resource_fancy(&mut self, path: &str, dti: &DTI) -> anyhow::Result<Resource> {
-        let (archive_path, path): (Option<&str>, &str) = path
-            .split_once(':')
-            .map(|(a, p)| (Some(a), p))
-            .unwrap_or((None, path));
+        let path = self.add_archive_from_path(path)?;
 
-        if let Some(archive_path) = archive_path {
-            self.add_archive(&PathBuf::from(archive_path))?;
+        self.get_resource(path, dti)
+    }
+
+    fn dti_file_ext(dti: &DTI) -> anyhow::Result<&str> {
+        dti.file_ext()
+            .ok_or_else(|| anyhow!("DTI {} doesn't have a file extension", dti.name()))
+    }
+
+    /// The mount point that get_resource would load a resource from
+    pub fn resource_layer(
+        &self,
+        path: impl Into<ResourcePath>,
+        dti: &DTI,
+    ) -> anyhow::Result<Option<&MountPoint>> {
+        let path = path.into();
+        Self::dti_file_ext(dti)?;
+
+        Ok(self
+            .mounts
+            .iter()
+            .find(|mount| mount.source.contains(&path, dti)))
+    }
+
+    /// Like resource_layer, but also finds types that aren't in the DTI table
+    pub fn resource_layer_by_hash(
+        &self,
+        path: impl Into<ResourcePath>,
+        dti_hash: u32,
+    ) -> Option<&MountPoint> {
+        let path = path.into();
+
+        self.mounts
+            .iter()
+            .find(|mount| mount.source.contains_hash(&path, dti_hash))
+    }
+
+    /// Paths are looked up ignoring case, even for loose files
+    pub fn get_resource(
+        &self,
+        path: impl Into<ResourcePath>,
+        dti: &DTI,
+    ) -> anyhow::Result<Resource> {
+        let path = path.into();
+        let file_ext = Self::dti_file_ext(dti)?;
+
+        for mount in &self.mounts {
+            if let Some(resource) = mount.source.open_resource(&path, dti)? {
+                trace!("loaded resource {:?} from {:?}", path, mount.path);
+                return Ok(resource);
+            }
         }
 
-        self.get_resource(&PathBuf::from(path), dti)
+        Err(anyhow!(
+            "Couldn't find resource {:?} ({})",
+            path.to_path_buf().with_extension(file_ext),
+            dti.name()
+        ))
     }
 
-    pub fn get_resource(&self, path: &Path, dti: &DTI) -> anyhow::Result<Resource> {
-        let file_ext = dti
-            .file_ext()
-            .ok_or_else(|| anyhow!("DTI {} doesn't have a file extension", dti.name(),))?;
+    /// Every resource that can be loaded, from the mount point it would be
+    /// loaded from. Sorted by path.
+    pub fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        let mut seen = HashSet::new();
+        let mut resources = vec![];
 
-        let fs_path = &self.base_path.join(path.with_extension(file_ext));
-        trace!(
-            "Attempting to load resource {:?} ({}) from file path {:?}",
-            path,
-            dti.name(),
-            fs_path
-        );
-        let file = std::fs::File::open(fs_path);
-
-        if let Ok(file) = file {
-            Ok(Resource(ResourceInner::FileBacked(BufReader::new(file))))
-        } else {
-            for archive in self.loaded_archives.values() {
-                if let Some(resource_data) = archive.get_resource_with_path(path, dti)? {
-                    return Ok(Resource(ResourceInner::ArchiveBacked(Cursor::new(
-                        resource_data,
-                    ))));
+        for mount in &self.mounts {
+            for resource in mount.resources()? {
+                if seen.insert((resource.path.clone(), resource.dti_hash)) {
+                    resources.push(resource);
                 }
             }
+        }
+
+        resources.sort_by(|a, b| (&a.path, a.dti_hash).cmp(&(&b.path, b.dti_hash)));
+
+        Ok(resources)
+    }
+
+    /// Every resource of a DTI, e.g. all nGO::rCharacter
+    pub fn resources_of_type(&self, dti: &DTI) -> anyhow::Result<Vec<ResourceEntry>> {
+        let mut resources = self.resources()?;
+        resources.retain(|resource| resource.dti_hash == dti.hash());
+
+        Ok(resources)
+    }
+
+    /// Every resource whose path (with extension, '/' separated) matches a
+    /// glob, ignoring case, e.g. "model/**/*.mod"
+    pub fn find_resources(&self, pattern: &str) -> anyhow::Result<Vec<ResourceEntry>> {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .literal_separator(true)
+            .build()?
+            .compile_matcher();
+
+        let mut resources = self.resources()?;
+        resources.retain(|resource| glob.is_match(resource.path_with_ext()));
+
+        Ok(resources)
+    }
+
+    /// Set the function used to parse resources of a DTI, replacing any
+    /// existing one
+    pub fn register_loader<T, F>(&mut self, dti: &DTI, loader: F)
+    where
+        T: Any + Send + Sync,
+        F: Fn(&mut Resource, &ResourceManager) -> anyhow::Result<T> + Send + Sync + 'static,
+    {
+        self.loaders.insert(
+            dti.hash(),
+            Box::new(move |reader, resource_manager| {
+                Ok(Arc::new(loader(reader, resource_manager)?))
+            }),
+        );
+    }
+
+    /// Load and parse a resource, or return the copy that was already loaded
+    pub fn load<T: TypedResource>(&self, path: impl Into<ResourcePath>) -> anyhow::Result<Arc<T>> {
+        self.load_with_dti(path, T::dti())
+    }
+
+    /// Like load, for types that are used by several DTIs (like
+    /// mtserializer::Class)
+    pub fn load_with_dti<T: Any + Send + Sync>(
+        &self,
+        path: impl Into<ResourcePath>,
+        dti: &DTI,
+    ) -> anyhow::Result<Arc<T>> {
+        let path = path.into();
+        let key = (path.clone(), dti.hash());
+
+        // Not held while loading, since loaders can load other resources
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        let loaded = match cached {
+            Some(loaded) => loaded,
+            None => {
+                let loaded = self.load_uncached(&path, dti).with_context(|| {
+                    let resolved_path = Self::dti_file_ext(dti)
+                        .map(|file_ext| path.to_path_buf().with_extension(file_ext))
+                        .unwrap_or_else(|_| path.to_path_buf());
+
+                    match self.resource_layer(&path, dti) {
+                        Ok(Some(mount)) => format!(
+                            "couldn't load {:?} ({}) from {:?}",
+                            resolved_path,
+                            dti.name(),
+                            mount.path()
+                        ),
+                        _ => format!("couldn't load {:?} ({})", resolved_path, dti.name()),
+                    }
+                })?;
+
+                self.cache.lock().unwrap().insert(key, loaded.clone());
+                loaded
+            }
+        };
+
+        loaded.downcast::<T>().map_err(|_| {
+            anyhow!(
+                "resource {:?} ({}) was loaded as a different type than {}",
+                path,
+                dti.name(),
+                std::any::type_name::<T>()
+            )
+        })
+    }
+
+    fn load_uncached(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<LoadedResource> {
+        let loader = self
+            .loaders
+            .get(&dti.hash())
+            .ok_or_else(|| anyhow!("no loader registered for DTI {}", dti.name()))?;
 
-            Err(anyhow!(
-                "Couldn't find resource {:?} ({})",
-                fs_path,
-                dti.name()
-            ))
+        let mut resource = self.get_resource(path, dti)?;
+        loader(&mut resource, self)
+    }
+
+    /// Forget every loaded resource, so they're loaded again next time
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+
+        for mount in &self.mounts {
+            mount.source.clear_cache();
         }
     }
 }
+
+#[test]
+fn test_mount_priority() {
+    use crate::rarchive::ArchiveWriter;
+
+    let temp_dir = crate::util::TempDir::new("resource_manager").unwrap();
+    let root = temp_dir.path();
+    let mod_dir = root.join("mod");
+    std::fs::create_dir_all(&mod_dir).unwrap();
+
+    let write_archive = |name: &str, data: &[u8]| {
+        let mut writer = ArchiveWriter::new();
+        writer.add_file("tex\\a", &DTIs::rTexture, 0, data).unwrap();
+        writer
+            .add_file(&format!("tex\\{}", name), &DTIs::rTexture, 0, data)
+            .unwrap();
+
+        let mut file = File::create(root.join(name).with_extension("arc")).unwrap();
+        writer.save(&mut file).unwrap();
+    };
+    write_archive("base", b"base");
+    write_archive("patch", b"patch");
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.add_archive(Path::new("base")).unwrap();
+    resource_manager
+        .mount_archive(
+            &root.join("patch.arc"),
+            ResourceManager::ARCHIVE_PRIORITY + 1,
+        )
+        .unwrap();
+
+    let read_resource = |resource_manager: &ResourceManager, path: &str| {
+        let mut data = vec![];
+        resource_manager
+            .get_resource(Path::new(path), &DTIs::rTexture)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+
+        data
+    };
+    let layer_path = |resource_manager: &ResourceManager, path: &str| {
+        resource_manager
+            .resource_layer(Path::new(path), &DTIs::rTexture)
+            .unwrap()
+            .map(|mount| mount.path().to_path_buf())
+    };
+
+    assert_eq!(
+        b"patch".as_slice(),
+        read_resource(&resource_manager, "tex/a")
+    );
+    assert_eq!(
+        b"base".as_slice(),
+        read_resource(&resource_manager, "tex/base")
+    );
+    assert_eq!(
+        Some(root.join("patch.arc")),
+        layer_path(&resource_manager, "tex/a")
+    );
+    assert_eq!(None, layer_path(&resource_manager, "tex/missing"));
+
+    // Loose files in a mod folder override everything
+    std::fs::create_dir_all(mod_dir.join("tex")).unwrap();
+    std::fs::write(mod_dir.join("tex/a.tex"), b"mod").unwrap();
+    resource_manager.mount_directory(&mod_dir, ResourceManager::DIRECTORY_PRIORITY + 1);
+
+    assert_eq!(b"mod".as_slice(), read_resource(&resource_manager, "tex/a"));
+    assert_eq!(Some(mod_dir), layer_path(&resource_manager, "tex/a"));
+}
+
+#[test]
+fn test_load_cached() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let temp_dir = crate::util::TempDir::new("resource_load").unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("tex")).unwrap();
+    std::fs::write(root.join("tex/a.tex"), b"data").unwrap();
+
+    let num_loads = Arc::new(AtomicUsize::new(0));
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.register_loader(&DTIs::rTexture, {
+        let num_loads = num_loads.clone();
+        move |reader, _| {
+            num_loads.fetch_add(1, Ordering::Relaxed);
+
+            let mut data = vec![];
+            reader.read_to_end(&mut data)?;
+            Ok(data)
+        }
+    });
+
+    let first = resource_manager
+        .load_with_dti::<Vec<u8>>(Path::new("tex/a"), &DTIs::rTexture)
+        .unwrap();
+    let second = resource_manager
+        .load_with_dti::<Vec<u8>>(Path::new("tex/a"), &DTIs::rTexture)
+        .unwrap();
+
+    assert_eq!(b"data".as_slice(), first.as_slice());
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(1, num_loads.load(Ordering::Relaxed));
+
+    // Errors say what was being loaded
+    let err = resource_manager
+        .load::<TextureFile>(Path::new("tex/a"))
+        .map(|_| ())
+        .unwrap_err();
+    assert!(err.to_string().contains("different type"));
+
+    let err = resource_manager
+        .load_with_dti::<Vec<u8>>(Path::new("tex/missing"), &DTIs::rTexture)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("\"tex/missing.tex\" (rTexture)"));
+
+    let err = resource_manager
+        .load::<MaterialFile>(Path::new("tex/a"))
+        .map(|_| ())
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("no loader registered for DTI rMaterial"));
+
+    // Mounting something that overrides a loaded resource loads it again
+    let mut patch = crate::resource_source::MemorySource::new();
+    patch.insert("tex\\a", &DTIs::rTexture, b"patched".as_slice());
+    resource_manager.mount(
+        Path::new("patch"),
+        ResourceManager::DIRECTORY_PRIORITY + 1,
+        patch,
+    );
+
+    let patched = resource_manager
+        .load_with_dti::<Vec<u8>>(Path::new("tex/a"), &DTIs::rTexture)
+        .unwrap();
+    assert_eq!(b"patched".as_slice(), patched.as_slice());
+    assert_eq!(2, num_loads.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_find_resources() {
+    use crate::rarchive::ArchiveWriter;
+
+    let temp_dir = crate::util::TempDir::new("resource_find").unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("tex")).unwrap();
+    std::fs::write(root.join("tex/a.tex"), b"loose").unwrap();
+    std::fs::write(root.join("Player.chr"), b"chr").unwrap();
+
+    let mut writer = ArchiveWriter::new();
+    writer
+        .add_file("tex\\a", &DTIs::rTexture, 0, b"archived")
+        .unwrap();
+    writer
+        .add_file("tex\\b", &DTIs::rTexture, 3, b"archived")
+        .unwrap();
+    writer
+        .save(&mut File::create(root.join("test.arc")).unwrap())
+        .unwrap();
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.add_archive(Path::new("test")).unwrap();
+
+    let textures = resource_manager.resources_of_type(&DTIs::rTexture).unwrap();
+    assert_eq!(2, textures.len());
+
+    // The loose file hides the archived one
+    assert_eq!(&ResourcePath::new("tex\\a"), textures[0].path());
+    assert_eq!(5, textures[0].size_uncompressed());
+    assert_eq!(None, textures[0].quality());
+    assert_eq!(root, textures[0].mount_path());
+
+    assert_eq!(&ResourcePath::new("tex\\b"), textures[1].path());
+    assert_eq!(Some(3), textures[1].quality());
+    assert_eq!(root.join("test.arc"), textures[1].mount_path());
+
+    // .chr is used by more than one DTI
+    let characters = resource_manager.find_resources("*.CHR").unwrap();
+    assert_eq!(2, characters.len());
+    assert!(characters
+        .iter()
+        .any(|resource| resource.dti() == Some(&DTIs::nGO__rCharacter)));
+
+    let found = resource_manager.find_resources("tex/*").unwrap();
+    assert_eq!(2, found.len());
+}
+
+#[test]
+fn test_case_insensitive_paths() {
+    let temp_dir = crate::util::TempDir::new("resource_case").unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("Model/Player")).unwrap();
+    std::fs::write(root.join("Model/Player/PL00.tex"), b"data").unwrap();
+
+    let resource_manager = ResourceManager::new(root);
+
+    let mut data = vec![];
+    resource_manager
+        .get_resource("model\\player\\pl00", &DTIs::rTexture)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(b"data".as_slice(), data);
+
+    assert!(resource_manager
+        .get_resource("model\\player\\pl01", &DTIs::rTexture)
+        .is_err());
+}
+
+#[test]
+fn test_watch_loose_files() {
+    use std::time::Duration;
+
+    let temp_dir = crate::util::TempDir::new("resource_watch").unwrap();
+    let root = temp_dir.path();
+    std::fs::create_dir_all(root.join("tex")).unwrap();
+    std::fs::write(root.join("tex/a.tex"), b"old").unwrap();
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.register_loader(&DTIs::rTexture, |reader, _| {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    });
+    let changes = resource_manager.watch_loose_files().unwrap();
+
+    let load = |resource_manager: &ResourceManager| {
+        resource_manager
+            .load_with_dti::<Vec<u8>>(Path::new("tex/a"), &DTIs::rTexture)
+            .unwrap()
+    };
+    assert_eq!(b"old".as_slice(), load(&resource_manager).as_slice());
+
+    std::fs::write(root.join("tex/a.tex"), b"new").unwrap();
+
+    let change = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(ResourcePath::new("tex\\a"), change.path);
+    assert_eq!(&DTIs::rTexture, change.dti);
+    assert_eq!(root, change.mount_path);
+
+    // Saving can take more than one event, wait for them all
+    while changes.recv_timeout(Duration::from_millis(100)).is_ok() {}
+    assert_eq!(b"new".as_slice(), load(&resource_manager).as_slice());
+}
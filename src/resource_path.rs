@@ -0,0 +1,115 @@
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+/// A path to a resource, the way the game refers to them: '\\' separated,
+/// without an extension, and compared ignoring (ASCII) case
+#[derive(Debug, Clone)]
+pub struct ResourcePath(String);
+
+impl ResourcePath {
+    pub fn new(path: &str) -> Self {
+        let path = path.replace('/', "\\");
+
+        Self(path.trim_start_matches('\\').to_string())
+    }
+
+    /// The path with '\\' separators, as it's stored in archives
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The path with '/' separators, for looking up loose files
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf::from(self.0.replace('\\', "/"))
+    }
+
+    fn lowercase_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.0.bytes().map(|b| b.to_ascii_lowercase())
+    }
+}
+
+impl PartialEq for ResourcePath {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for ResourcePath {}
+
+impl Hash for ResourcePath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for b in self.lowercase_bytes() {
+            state.write_u8(b);
+        }
+
+        // Like str, so that tuples containing paths don't collide as easily
+        state.write_u8(0xff);
+    }
+}
+
+impl PartialOrd for ResourcePath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ResourcePath {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.lowercase_bytes().cmp(other.lowercase_bytes())
+    }
+}
+
+impl Display for ResourcePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for ResourcePath {
+    fn from(path: &str) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&String> for ResourcePath {
+    fn from(path: &String) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&Path> for ResourcePath {
+    fn from(path: &Path) -> Self {
+        Self::new(&path.to_string_lossy())
+    }
+}
+
+impl From<&PathBuf> for ResourcePath {
+    fn from(path: &PathBuf) -> Self {
+        Self::from(path.as_path())
+    }
+}
+
+impl From<&ResourcePath> for ResourcePath {
+    fn from(path: &ResourcePath) -> Self {
+        path.clone()
+    }
+}
+
+#[test]
+fn test_resource_path() {
+    use std::collections::HashSet;
+
+    let path = ResourcePath::new("Model/Player\\PL00");
+    assert_eq!("Model\\Player\\PL00", path.as_str());
+    assert_eq!(PathBuf::from("Model/Player/PL00"), path.to_path_buf());
+
+    assert_eq!(ResourcePath::new("model\\player\\pl00"), path);
+    assert_ne!(ResourcePath::new("model\\player\\pl01"), path);
+
+    let set: HashSet<ResourcePath> = [path].into_iter().collect();
+    assert!(set.contains(&ResourcePath::from(Path::new("MODEL/PLAYER/PL00"))));
+}
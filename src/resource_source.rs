@@ -0,0 +1,539 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use log::trace;
+use walkdir::WalkDir;
+use zip::ZipArchive;
+
+use crate::{
+    archive_index::ArchiveIndex,
+    rarchive::{file_ext_for_dti_hash, ArchiveFile},
+    resource_manager::{Resource, ResourceEntry},
+    resource_path::ResourcePath,
+    DTI,
+};
+
+/// Where a mount point's resources come from. Anything that implements this
+/// can be mounted with ResourceManager::mount.
+pub trait ResourceSource: Any + Send + Sync {
+    /// Open a resource, or return None if this source doesn't have it
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>>;
+
+    /// Whether open_resource would find a resource
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool;
+
+    /// Like contains, but also for types that aren't in the DTI table
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        DTI::from_hash(dti_hash).is_some_and(|dti| self.contains(path, dti))
+    }
+
+    /// Every resource in this source. Mount paths are filled in by the mount
+    /// point.
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>>;
+
+    /// For loose files on disk, the directory that's watched for changes
+    fn loose_files_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Forget anything that was cached about the source's contents
+    fn clear_cache(&self) {}
+}
+
+// Finds the resources a loose file could be, from its path relative to the
+// mount point. Several DTIs can have the same extension, and some extensions
+// have more than one part.
+pub(crate) struct LooseFileTypes {
+    // File extension -> DTIs with it
+    by_file_ext: HashMap<&'static str, Vec<&'static DTI>>,
+}
+
+impl LooseFileTypes {
+    // DTIs can be loaded at runtime, so this isn't a static
+    pub(crate) fn new() -> Self {
+        let mut by_file_ext: HashMap<&'static str, Vec<&'static DTI>> = HashMap::new();
+        for dti in DTI::all() {
+            if let Some(file_ext) = dti.file_ext() {
+                by_file_ext.entry(file_ext).or_default().push(dti);
+            }
+        }
+
+        Self { by_file_ext }
+    }
+
+    pub(crate) fn resources(&self, relative_path: &Path) -> Vec<(ResourcePath, &'static DTI)> {
+        let file_name = relative_path.to_string_lossy().replace('\\', "/");
+        let base_name_start = file_name.rfind('/').map_or(0, |idx| idx + 1);
+
+        let mut resources = vec![];
+        for (dot_idx, _) in file_name[base_name_start..].match_indices('.') {
+            let dot_idx = base_name_start + dot_idx;
+            let Some(dtis) = self.by_file_ext.get(&file_name[dot_idx + 1..]) else {
+                continue;
+            };
+
+            let path = PathBuf::from(&file_name[..dot_idx]);
+            for dti in dtis {
+                let file_ext = dti.file_ext().unwrap();
+                if path.with_extension(file_ext).to_string_lossy() == file_name {
+                    resources.push((ResourcePath::from(&path), *dti));
+                }
+            }
+        }
+
+        resources
+    }
+}
+
+// Lowercase file name -> actual file name
+type DirectoryListing = HashMap<String, OsString>;
+
+// Directory mtimes can be coarse, a listing read right after a change might
+// miss another change that gets the same mtime
+const LISTING_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+struct CachedListing {
+    modified: SystemTime,
+    // Whether the listing was read long enough after the directory last
+    // changed, so that any later change gives it a different mtime
+    settled: bool,
+    listing: Arc<DirectoryListing>,
+}
+
+/// Loose files, named like their resource path with the DTI's file extension
+pub struct DirectorySource {
+    path: PathBuf,
+
+    // For looking up loose files ignoring case. Listings are read again when
+    // the directory's mtime changes, missing directories aren't cached.
+    directory_listings: Mutex<HashMap<PathBuf, CachedListing>>,
+}
+
+impl DirectorySource {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            directory_listings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn directory_listing(&self, dir: &Path) -> Option<Arc<DirectoryListing>> {
+        let modified = std::fs::metadata(dir).ok()?.modified().ok()?;
+
+        let mut directory_listings = self.directory_listings.lock().unwrap();
+        if let Some(cached) = directory_listings.get(dir) {
+            if cached.settled && cached.modified == modified {
+                return Some(cached.listing.clone());
+            }
+        }
+
+        let read_time = SystemTime::now();
+        let listing: DirectoryListing = std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let file_name = entry.file_name();
+                (file_name.to_string_lossy().to_ascii_lowercase(), file_name)
+            })
+            .collect();
+        let listing = Arc::new(listing);
+
+        directory_listings.insert(
+            dir.to_path_buf(),
+            CachedListing {
+                modified,
+                settled: read_time
+                    .duration_since(modified)
+                    .is_ok_and(|age| age > LISTING_SETTLE_TIME),
+                listing: listing.clone(),
+            },
+        );
+
+        Some(listing)
+    }
+
+    // Game paths don't always match the case of the files on disk, so if
+    // there's no exact match, each component is looked up ignoring case
+    fn loose_file_path(&self, path: &ResourcePath, file_ext: &str) -> Option<PathBuf> {
+        let relative_path = path.to_path_buf().with_extension(file_ext);
+
+        let fs_path = self.path.join(&relative_path);
+        if fs_path.is_file() {
+            return Some(fs_path);
+        }
+
+        let mut fs_path = self.path.clone();
+        for component in relative_path.components() {
+            let file_name = component.as_os_str().to_string_lossy().to_ascii_lowercase();
+            fs_path.push(self.directory_listing(&fs_path)?.get(&file_name)?);
+        }
+
+        fs_path.is_file().then_some(fs_path)
+    }
+}
+
+impl ResourceSource for DirectorySource {
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>> {
+        let Some(fs_path) = dti
+            .file_ext()
+            .and_then(|file_ext| self.loose_file_path(path, file_ext))
+        else {
+            return Ok(None);
+        };
+        trace!(
+            "Attempting to load resource {:?} ({}) from file path {:?}",
+            path,
+            dti.name(),
+            fs_path
+        );
+
+        match File::open(fs_path) {
+            Ok(file) => Ok(Some(Resource::new(BufReader::new(file)))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+        dti.file_ext()
+            .is_some_and(|file_ext| self.loose_file_path(path, file_ext).is_some())
+    }
+
+    // Unknown types are unpacked with the hash as the extension
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        self.loose_file_path(path, &file_ext_for_dti_hash(dti_hash))
+            .is_some()
+    }
+
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        let loose_file_types = LooseFileTypes::new();
+
+        let mut resources = vec![];
+        for file in WalkDir::new(&self.path) {
+            let file = file?;
+            if !file.file_type().is_file() {
+                continue;
+            }
+
+            let relative_path = file.path().strip_prefix(&self.path)?;
+            for (path, dti) in loose_file_types.resources(relative_path) {
+                resources.push(ResourceEntry::new(path, dti.hash(), file.metadata()?.len()));
+            }
+        }
+
+        Ok(resources)
+    }
+
+    fn loose_files_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn clear_cache(&self) {
+        self.directory_listings.lock().unwrap().clear();
+    }
+}
+
+impl ResourceSource for ArchiveFile<File> {
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>> {
+        Ok(ArchiveFile::open_resource(self, path.as_str(), dti)?.map(Resource::new))
+    }
+
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+        self.resource_info(path.as_str(), dti).is_some()
+    }
+
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        self.resource_info_by_hash(path.as_str(), dti_hash)
+            .is_some()
+    }
+
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        Ok(self
+            .resource_infos()
+            .iter()
+            .map(|info| {
+                ResourceEntry::new(
+                    ResourcePath::new(info.path()),
+                    info.dti_hash(),
+                    info.size_uncompressed() as u64,
+                )
+                .with_archive_info(info.size_compressed(), info.quality())
+            })
+            .collect())
+    }
+}
+
+impl ResourceSource for ArchiveIndex {
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>> {
+        Ok(ArchiveIndex::open_resource(self, path, dti.hash())?.map(Resource::new))
+    }
+
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+        self.contains_hash(path, dti.hash())
+    }
+
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        self.archive_path(path, dti_hash).is_some()
+    }
+
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        Ok(self
+            .resource_infos()
+            .map(|(_, info)| {
+                ResourceEntry::new(
+                    ResourcePath::new(&info.path),
+                    info.dti_hash,
+                    info.size_uncompressed as u64,
+                )
+                .with_archive_info(info.size_compressed, info.quality)
+            })
+            .collect())
+    }
+}
+
+/// Loose files in a zip file, laid out like they would be in a directory
+pub struct ZipSource {
+    archive: Mutex<ZipArchive<File>>,
+    // (path, DTI hash) -> (index in the zip, uncompressed size)
+    entries: HashMap<(ResourcePath, u32), (usize, u64)>,
+}
+
+impl ZipSource {
+    pub fn open(fs_path: &Path) -> anyhow::Result<Self> {
+        let mut archive = ZipArchive::new(File::open(fs_path)?)?;
+
+        let loose_file_types = LooseFileTypes::new();
+
+        let mut entries = HashMap::new();
+        for idx in 0..archive.len() {
+            let file = archive.by_index_raw(idx)?;
+            if file.is_dir() {
+                continue;
+            }
+
+            for (path, dti) in loose_file_types.resources(Path::new(file.name())) {
+                entries.insert((path, dti.hash()), (idx, file.size()));
+            }
+        }
+
+        Ok(Self {
+            archive: Mutex::new(archive),
+            entries,
+        })
+    }
+}
+
+impl ResourceSource for ZipSource {
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>> {
+        let Some((idx, size)) = self.entries.get(&(path.clone(), dti.hash())) else {
+            return Ok(None);
+        };
+
+        // Zip entries can't be seeked, so they're decompressed up front
+        let mut data = Vec::with_capacity(*size as usize);
+        self.archive
+            .lock()
+            .unwrap()
+            .by_index(*idx)?
+            .read_to_end(&mut data)?;
+
+        Ok(Some(Resource::new(Cursor::new(data))))
+    }
+
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+        self.contains_hash(path, dti.hash())
+    }
+
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        self.entries.contains_key(&(path.clone(), dti_hash))
+    }
+
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        Ok(self
+            .entries
+            .iter()
+            .map(|((path, dti_hash), (_, size))| ResourceEntry::new(path.clone(), *dti_hash, *size))
+            .collect())
+    }
+}
+
+/// Resources that are kept in memory, e.g. for tests
+#[derive(Default)]
+pub struct MemorySource {
+    resources: HashMap<(ResourcePath, u32), Arc<[u8]>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a resource, replacing any existing one with the same path and DTI
+    pub fn insert(&mut self, path: impl Into<ResourcePath>, dti: &DTI, data: impl Into<Arc<[u8]>>) {
+        self.resources
+            .insert((path.into(), dti.hash()), data.into());
+    }
+}
+
+impl ResourceSource for MemorySource {
+    fn open_resource(&self, path: &ResourcePath, dti: &DTI) -> anyhow::Result<Option<Resource>> {
+        Ok(self
+            .resources
+            .get(&(path.clone(), dti.hash()))
+            .map(|data| Resource::new(Cursor::new(data.clone()))))
+    }
+
+    fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+        self.contains_hash(path, dti.hash())
+    }
+
+    fn contains_hash(&self, path: &ResourcePath, dti_hash: u32) -> bool {
+        self.resources.contains_key(&(path.clone(), dti_hash))
+    }
+
+    fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+        Ok(self
+            .resources
+            .iter()
+            .map(|((path, dti_hash), data)| {
+                ResourceEntry::new(path.clone(), *dti_hash, data.len() as u64)
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn test_resource_sources() {
+    use std::io::Write;
+
+    use crate::{resource_manager::ResourceManager, DTIs};
+
+    let temp_dir = crate::util::TempDir::new("resource_sources").unwrap();
+    let root = temp_dir.path();
+
+    let zip_path = root.join("mod.zip");
+    let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+    for (name, data) in [("Tex/A.tex", b"zip a"), ("tex/b.tex", b"zip b")] {
+        zip.start_file(name, Default::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let mut memory = MemorySource::new();
+    memory.insert("tex\\a", &DTIs::rTexture, b"memory a".as_slice());
+
+    // Only has one resource, which it makes up
+    struct CustomSource;
+    impl ResourceSource for CustomSource {
+        fn open_resource(
+            &self,
+            path: &ResourcePath,
+            dti: &DTI,
+        ) -> anyhow::Result<Option<Resource>> {
+            Ok(self
+                .contains(path, dti)
+                .then(|| Resource::new(Cursor::new(b"custom c".to_vec()))))
+        }
+
+        fn contains(&self, path: &ResourcePath, dti: &DTI) -> bool {
+            *path == ResourcePath::new("tex\\c") && *dti == DTIs::rTexture
+        }
+
+        fn resources(&self) -> anyhow::Result<Vec<ResourceEntry>> {
+            Ok(vec![ResourceEntry::new(
+                ResourcePath::new("tex\\c"),
+                DTIs::rTexture.hash(),
+                8,
+            )])
+        }
+    }
+
+    let mut resource_manager = ResourceManager::new(root);
+    resource_manager.mount_zip(&zip_path, 1).unwrap();
+    resource_manager.mount(Path::new("memory"), 2, memory);
+    resource_manager.mount(Path::new("custom"), 0, CustomSource);
+
+    let read_resource = |path: &str| {
+        let mut data = vec![];
+        resource_manager
+            .get_resource(path, &DTIs::rTexture)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+
+        String::from_utf8(data).unwrap()
+    };
+
+    assert_eq!("memory a", read_resource("tex\\a"));
+    assert_eq!("zip b", read_resource("TEX\\B"));
+    assert_eq!("custom c", read_resource("tex\\c"));
+
+    let mount_paths: Vec<_> = resource_manager
+        .resources()
+        .unwrap()
+        .into_iter()
+        .map(|resource| resource.mount_path().to_path_buf())
+        .collect();
+    assert_eq!(
+        vec![PathBuf::from("memory"), zip_path, PathBuf::from("custom")],
+        mount_paths
+    );
+
+    // After the base path
+    let memory_mount = &resource_manager.mounts()[1];
+    assert!(memory_mount.source_as::<MemorySource>().is_some());
+    assert!(memory_mount.source_as::<ZipSource>().is_none());
+}
+
+#[test]
+fn test_loose_file_types() {
+    let loose_file_types = LooseFileTypes::new();
+
+    assert_eq!(
+        vec![(ResourcePath::new("tex\\a"), &crate::DTIs::rTexture)],
+        loose_file_types.resources(Path::new("tex/a.tex"))
+    );
+    assert!(loose_file_types.resources(Path::new("tex/a")).is_empty());
+    assert!(loose_file_types
+        .resources(Path::new("tex.tex/a"))
+        .is_empty());
+
+    // Every DTI with the extension, including ones with several parts
+    let resources = loose_file_types.resources(Path::new("movie/a.mem.wmv"));
+    let dtis: Vec<_> = DTI::all()
+        .filter(|dti| dti.file_ext() == Some("mem.wmv"))
+        .collect();
+    assert!(!dtis.is_empty());
+    assert_eq!(dtis.len(), resources.len());
+    for (path, dti) in resources {
+        assert_eq!(ResourcePath::new("movie\\a"), path);
+        assert!(dtis.contains(&dti));
+    }
+}
+
+#[test]
+fn test_directory_source_new_files() {
+    use crate::DTIs;
+
+    let temp_dir = crate::util::TempDir::new("directory_source").unwrap();
+    let root = temp_dir.path();
+    let source = DirectorySource::new(root);
+
+    // Neither the directory nor the file exist yet
+    assert!(!source.contains(&ResourcePath::new("tex\\a"), &DTIs::rTexture));
+
+    std::fs::create_dir(root.join("Tex")).unwrap();
+    std::fs::write(root.join("Tex/A.tex"), b"a").unwrap();
+    assert!(source.contains(&ResourcePath::new("tex\\a"), &DTIs::rTexture));
+
+    // The listing of Tex was just read, adding to it is still noticed
+    std::fs::write(root.join("Tex/B.tex"), b"b").unwrap();
+    assert!(source.contains(&ResourcePath::new("tex\\b"), &DTIs::rTexture));
+}
@@ -4,10 +4,11 @@ use std::{
     mem::size_of,
 };
 
+use anyhow::anyhow;
 use log::{debug, warn};
 use zerocopy::{FromBytes, FromZeroes};
 
-use crate::{rshader2::Shader2File, util, DTI};
+use crate::{rshader2::Shader2File, util, DTIs, DTI};
 
 #[repr(C, packed)]
 #[derive(FromBytes, FromZeroes, Debug)]
@@ -37,19 +38,10 @@ struct RawTextureInfo {
 }
 
 impl RawTextureInfo {
-    fn path(&self) -> &str {
-        CStr::from_bytes_until_nul(&self.path)
-            .expect("failed to decode texture info path into CStr")
-            .to_str()
-            .expect("failed to convert texture info path into str")
-    }
-
-    fn dti(&self) -> Option<&DTI> {
-        if self.dti_hash != 0 {
-            Some(DTI::from_hash(self.dti_hash).expect("invalid DTI hash in texture info"))
-        } else {
-            None
-        }
+    fn path(&self) -> anyhow::Result<&str> {
+        Ok(CStr::from_bytes_until_nul(&self.path)
+            .map_err(|_| anyhow!("texture info path isn't null terminated"))?
+            .to_str()?)
     }
 }
 
@@ -181,24 +173,20 @@ impl MaterialFile {
 
         debug!("material header: {:#?}", header);
 
-        reader.seek(std::io::SeekFrom::Start(header.textures))?;
-        let textures: Vec<_> = (0..header.texture_num)
-            .map(|i| {
-                let texture_info: RawTextureInfo = util::read_struct(reader)?;
-
-                let texture_path = texture_info.path();
-                let texture_dti = texture_info.dti();
-                assert_eq!(texture_dti.map(|d| d.name()), Some("rTexture")); // HACK
-                debug!(
-                    "texture {}: dti {:?} path \"{}\"",
-                    i,
-                    texture_dti.map(|d| d.name()),
-                    texture_path
-                );
+        let textures = Self::read_texture_infos(reader, &header)?
+            .into_iter()
+            .map(|(dti_hash, path)| {
+                if dti_hash != DTIs::rTexture.hash() {
+                    return Err(anyhow!(
+                        "texture {:?} has DTI {:08x}, expected rTexture",
+                        path,
+                        dti_hash
+                    ));
+                }
 
-                Ok(texture_path.to_string())
+                Ok(path)
             })
-            .collect::<anyhow::Result<Vec<String>>>()?;
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
         let materials: Vec<_> = (0..header.material_num).map(|material_idx | {
             reader.seek(std::io::SeekFrom::Start(
@@ -297,6 +285,37 @@ impl MaterialFile {
         })
     }
 
+    /// Just the textures a material uses, as (DTI hash, path), which (unlike
+    /// the rest of the file) can be read without the rShader2
+    pub fn read_textures<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Vec<(u32, String)>> {
+        let header: MaterialHeader = util::read_struct(reader)?;
+
+        Self::read_texture_infos(reader, &header)
+    }
+
+    fn read_texture_infos<R: Read + Seek>(
+        reader: &mut R,
+        header: &MaterialHeader,
+    ) -> anyhow::Result<Vec<(u32, String)>> {
+        reader.seek(std::io::SeekFrom::Start(header.textures))?;
+        (0..header.texture_num)
+            .map(|i| {
+                let texture_info: RawTextureInfo = util::read_struct(reader)?;
+
+                let texture_path = texture_info.path()?;
+                let dti_hash = texture_info.dti_hash;
+                debug!(
+                    "texture {}: dti {:?} path \"{}\"",
+                    i,
+                    DTI::from_hash(dti_hash).map(|d| d.name()),
+                    texture_path
+                );
+
+                Ok((dti_hash, texture_path.to_string()))
+            })
+            .collect()
+    }
+
     pub fn textures(&self) -> &[String] {
         &self.textures
     }
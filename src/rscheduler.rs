@@ -80,7 +80,10 @@ struct SchedulerHeader {
 }
 
 #[derive(Debug)]
-pub struct SchedulerFile {}
+pub struct SchedulerFile {
+    // (DTI hash, path) of every TYPE_RESOURCE key
+    resources: Vec<(u32, String)>,
+}
 
 impl SchedulerFile {
     pub fn new<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Self> {
@@ -94,6 +97,8 @@ impl SchedulerFile {
         assert_eq!(header.magic.to_ne_bytes(), "SDL\0".as_bytes());
         assert_eq!({ header.version }, 0x16);
 
+        let mut resources = vec![];
+
         let tracks = util::read_struct_array::<SchedulerTrack>(
             &file_data[size_of::<SchedulerHeader>()..],
             header.track_num.into(),
@@ -202,6 +207,7 @@ impl SchedulerFile {
                                     let path = CStr::from_bytes_until_nul(path_bytes)?;
 
                                     debug!("\t\tvalue: resource {} {:?}", dti, path);
+                                    resources.push((dti, path.to_str()?.to_string()));
                                 }
                             }
                             _ => todo!("handle type {:?}", track_type),
@@ -214,7 +220,12 @@ impl SchedulerFile {
             }
         }
 
-        Ok(Self {})
+        Ok(Self { resources })
+    }
+
+    /// (DTI hash, path) of the resources the scheduler's keys refer to
+    pub fn resources(&self) -> &[(u32, String)] {
+        &self.resources
     }
 }
 
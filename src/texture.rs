@@ -8,7 +8,7 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, resource: TextureFile) -> Self {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, resource: &TextureFile) -> Self {
         let texture = device.create_texture_with_data(
             queue,
             &wgpu::TextureDescriptor {
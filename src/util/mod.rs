@@ -1,6 +1,7 @@
 mod read_struct;
 mod hexdump;
 mod crc;
+mod temp_dir;
 
 #[macro_export]
 macro_rules! get_enum_value {
@@ -17,3 +18,4 @@ pub use get_enum_value;
 pub use read_struct::*;
 pub use hexdump::*;
 pub use crc::*;
+pub use temp_dir::*;